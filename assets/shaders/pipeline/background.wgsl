@@ -1,12 +1,31 @@
 import package::uniform::{
     camera_shader::camera,
-    fog_shader::{fog, fog_main}
+    fog_shader::fog_color
 };
 
+struct Background {
+    zenith: vec4<f32>,
+    horizon: vec4<f32>,
+    ground: vec4<f32>,
+    // 0 fog, 1 sky, 2 skybox
+    mode: u32,
+    falloff: f32,
+    stars: f32,
+    star_brightness: f32,
+    intensity: f32,
+    horizon_fog: f32,
+};
+
+@group(1) @binding(0) var<uniform> background: Background;
+@group(1) @binding(1) var skybox: texture_cube<f32>;
+@group(1) @binding(2) var skybox_sampler: sampler;
+
+// Star field cells across the sky, more cells make smaller stars
+const STAR_SCALE: f32 = 120.0;
+
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
-    @location(0) screen_t: f32,
-    @location(1) view_depth: f32,
+    @location(0) ndc: vec2<f32>,
 };
 
 @vertex
@@ -16,21 +35,76 @@ fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
         vec2(3.0, -1.0),
         vec2(-1.0, 3.0)
     );
-    
+
     let pos = positions[vertex_index];
-    let clip_pos = vec4(pos, 0.0, 1.0);
-    
-    let screen_t = (pos.y + 1.0) * 0.5;
-    
-    return VertexOutput(
-        clip_pos,
-        screen_t,
-        1000.0
+    return VertexOutput(vec4(pos, 0.0, 1.0), pos);
+}
+
+// World space direction through the pixel. Depth is reversed, 1 is the near
+// plane and 0.5 lies behind it for both projections.
+fn view_direction(ndc: vec2<f32>) -> vec3<f32> {
+    let near = camera.inv_view_proj * vec4(ndc, 1.0, 1.0);
+    let far = camera.inv_view_proj * vec4(ndc, 0.5, 1.0);
+    return normalize(far.xyz / far.w - near.xyz / near.w);
+}
+
+fn hash3(p: vec3<f32>) -> vec3<f32> {
+    let q = vec3(
+        dot(p, vec3(127.1, 311.7, 74.7)),
+        dot(p, vec3(269.5, 183.3, 246.1)),
+        dot(p, vec3(113.5, 271.9, 124.6))
     );
+    return fract(sin(q) * 43758.5453);
+}
+
+fn star_field(dir: vec3<f32>) -> f32 {
+    let p = dir * STAR_SCALE;
+    let cell = floor(p);
+    let h = hash3(cell);
+    if h.z >= background.stars {
+        return 0.0;
+    }
+
+    // One star per lit cell, somewhere away from the cell borders
+    let center = cell + 0.25 + h * 0.5;
+    let glow = smoothstep(0.15, 0.0, length(p - center));
+    // Stars fade out towards the horizon
+    let height = smoothstep(0.0, 0.2, dir.y);
+    return glow * height * mix(0.2, 1.0, h.x) * background.star_brightness;
+}
+
+fn sky(dir: vec3<f32>) -> vec3<f32> {
+    let up = dir.y;
+    var color: vec3<f32>;
+    if up >= 0.0 {
+        color = mix(background.horizon.rgb, background.zenith.rgb, 1.0 - pow(1.0 - up, background.falloff));
+    } else {
+        color = mix(background.horizon.rgb, background.ground.rgb, 1.0 - pow(1.0 + up, background.falloff));
+    }
+    return color + star_field(dir);
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let fog_value = fog_main(in.screen_t, in.view_depth);
-    return fog_value.color * fog_value.factor;
-}
\ No newline at end of file
+    let dir = view_direction(in.ndc);
+    // The background is past any fog distance, fully fogged
+    let haze = fog_color(dir);
+
+    let cubemap = textureSample(skybox, skybox_sampler, dir).rgb * background.intensity;
+
+    var color: vec3<f32>;
+    switch background.mode {
+        case 1u: {
+            color = sky(dir);
+        }
+        case 2u: {
+            color = cubemap;
+        }
+        default: {
+            return vec4(haze.rgb, 1.0);
+        }
+    }
+
+    let horizon = pow(1.0 - abs(dir.y), 8.0) * background.horizon_fog;
+    return vec4(mix(color, haze.rgb, horizon), 1.0);
+}
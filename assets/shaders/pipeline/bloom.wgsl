@@ -0,0 +1,82 @@
+struct Bloom {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+};
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+@group(0) @binding(2) var<uniform> bloom: Bloom;
+
+struct VertexOutput {
+    @location(0) uv: vec2<f32>,
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // Generate a triangle that covers the whole screen
+    out.uv = vec2<f32>(
+        f32((vi << 1u) & 2u),
+        f32(vi & 2u),
+    );
+    out.clip_position = vec4<f32>(out.uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv.y = 1.0 - out.uv.y;
+    return out;
+}
+
+// Dual filter downsample, the bilinear taps cover a 4x4 texel area
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+
+    var color = textureSample(source, source_sampler, uv).rgb * 4.0;
+    color += textureSample(source, source_sampler, uv + vec2(-texel.x, -texel.y)).rgb;
+    color += textureSample(source, source_sampler, uv + vec2(texel.x, -texel.y)).rgb;
+    color += textureSample(source, source_sampler, uv + vec2(-texel.x, texel.y)).rgb;
+    color += textureSample(source, source_sampler, uv + vec2(texel.x, texel.y)).rgb;
+    return color / 8.0;
+}
+
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = downsample(in.uv);
+    let brightness = max(color.r, max(color.g, color.b));
+
+    // Soft knee around the threshold, so the cutoff doesn't show
+    var soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+    soft = soft * soft / (4.0 * bloom.knee + 0.0001);
+    let contribution = max(soft, brightness - bloom.threshold) / max(brightness, 0.0001);
+
+    return vec4(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(downsample(in.uv), 1.0);
+}
+
+// Dual filter upsample, a tent over the 3x3 neighbourhood
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let uv = in.uv;
+
+    var color = textureSample(source, source_sampler, uv + vec2(-texel.x * 2.0, 0.0)).rgb;
+    color += textureSample(source, source_sampler, uv + vec2(texel.x * 2.0, 0.0)).rgb;
+    color += textureSample(source, source_sampler, uv + vec2(0.0, -texel.y * 2.0)).rgb;
+    color += textureSample(source, source_sampler, uv + vec2(0.0, texel.y * 2.0)).rgb;
+    color += textureSample(source, source_sampler, uv + vec2(-texel.x, -texel.y)).rgb * 2.0;
+    color += textureSample(source, source_sampler, uv + vec2(texel.x, -texel.y)).rgb * 2.0;
+    color += textureSample(source, source_sampler, uv + vec2(-texel.x, texel.y)).rgb * 2.0;
+    color += textureSample(source, source_sampler, uv + vec2(texel.x, texel.y)).rgb * 2.0;
+    return vec4(color / 12.0, 1.0);
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv).rgb;
+    return vec4(color * bloom.intensity, 0.0);
+}
@@ -0,0 +1,77 @@
+struct Luminance {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    dt: f32,
+    adaptation_speed: f32,
+    pixel_count: u32,
+};
+
+const BINS: u32 = 256u;
+
+@group(0) @binding(0) var hdr_image: texture_2d<f32>;
+@group(0) @binding(1) var<storage, read_write> histogram: array<atomic<u32>, BINS>;
+// Adapted average luminance, read by the exposure pass
+@group(0) @binding(2) var<storage, read_write> average: f32;
+@group(0) @binding(3) var<uniform> luminance: Luminance;
+
+var<workgroup> local_histogram: array<atomic<u32>, BINS>;
+var<workgroup> weighted: array<f32, BINS>;
+
+// Bin 0 collects black pixels, the rest covers the log2 luminance range
+fn bin(color: vec3<f32>) -> u32 {
+    let lum = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    if lum < 0.005 {
+        return 0u;
+    }
+
+    let log_lum = clamp(
+        (log2(lum) - luminance.min_log_luminance) / luminance.log_luminance_range,
+        0.0,
+        1.0,
+    );
+    return u32(log_lum * 254.0 + 1.0);
+}
+
+@compute @workgroup_size(16, 16)
+fn cs_histogram(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    atomicStore(&local_histogram[index], 0u);
+    workgroupBarrier();
+
+    if all(id.xy < textureDimensions(hdr_image)) {
+        let color = textureLoad(hdr_image, id.xy, 0).rgb;
+        atomicAdd(&local_histogram[bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[index], atomicLoad(&local_histogram[index]));
+}
+
+@compute @workgroup_size(256)
+fn cs_average(@builtin(local_invocation_index) index: u32) {
+    let count = atomicLoad(&histogram[index]);
+    weighted[index] = f32(count) * f32(index);
+    // Ready for the next frame
+    atomicStore(&histogram[index], 0u);
+    workgroupBarrier();
+
+    for (var stride = BINS / 2u; stride > 0u; stride >>= 1u) {
+        if index < stride {
+            weighted[index] += weighted[index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if index == 0u {
+        // `count` is the black bin here, those pixels don't count
+        let lit = max(f32(luminance.pixel_count) - f32(count), 1.0);
+        let mean_bin = weighted[0] / lit;
+        let log_lum = (mean_bin - 1.0) / 254.0 * luminance.log_luminance_range
+            + luminance.min_log_luminance;
+
+        let adaptation = 1.0 - exp(-luminance.dt * luminance.adaptation_speed);
+        average += (exp2(log_lum) - average) * adaptation;
+    }
+}
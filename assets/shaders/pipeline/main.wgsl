@@ -25,8 +25,6 @@ struct VertexOutput {
     @location(0) color: vec3<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) world_position: vec3<f32>,
-    @location(3) screen_t: f32,
-    @location(4) view_depth: f32,
 };
 
 @vertex
@@ -52,17 +50,12 @@ fn vs_main(
     out.world_normal = normalize(normal_matrix * model.normal);
     out.color = model.color;
     
-    let ndc_pos = out.clip_position.xy / out.clip_position.w;
-    out.screen_t = (ndc_pos.y + 1.0) * 0.5;
-    
-    out.view_depth = distance(out.world_position, camera.view_pos.xyz);
-    
     return out;
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let fog_value = fog_main(in.screen_t, in.view_depth);
+    let fog_value = fog_main(in.world_position - camera.view_pos.xyz);
     
     let object_color = light_main(in.world_position, in.world_normal) * in.color;
     let fogged_color = object_color * (1.0 - fog_value.factor) + fog_value.color.rgb * fog_value.factor;
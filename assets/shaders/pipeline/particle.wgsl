@@ -0,0 +1,154 @@
+import package::uniform::{
+    camera_shader::camera,
+    fog_shader::{fog, fog_main}
+};
+
+struct ParticleInput {
+    // xyz and the age in seconds
+    @location(0) position: vec4<f32>,
+    // xyz and the lifetime in seconds
+    @location(1) velocity: vec4<f32>,
+    @location(2) color_start: vec4<f32>,
+    @location(3) color_end: vec4<f32>,
+    // Start size, end size, drag and gravity
+    @location(4) params: vec4<f32>,
+    // Spin axis and radians per second
+    @location(5) spin: vec4<f32>,
+    // Shape and blend mode
+    @location(6) kind: vec2<u32>,
+    // Rotation and stretch
+    @location(7) motion: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) @interpolate(flat) shape: u32,
+    // From the camera to the vertex
+    @location(3) view_offset: vec3<f32>,
+};
+
+const BILLBOARD: u32 = 0u;
+const STREAK: u32 = 1u;
+const SHARD: u32 = 2u;
+
+const ADDITIVE: u32 = 0u;
+const ALPHA: u32 = 1u;
+
+// Rodrigues' rotation of `v` around the normalized `axis`
+fn rotate(v: vec3<f32>, axis: vec3<f32>, angle: f32) -> vec3<f32> {
+    let c = cos(angle);
+    let s = sin(angle);
+    return v * c + cross(axis, v) * s + axis * dot(axis, v) * (1.0 - c);
+}
+
+fn particle_vertex(vertex_index: u32, particle: ParticleInput, blend: u32) -> VertexOutput {
+    var out: VertexOutput;
+
+    let age = particle.position.w;
+    let lifetime = particle.velocity.w;
+    if age >= lifetime || particle.kind.y != blend {
+        // Every vertex in the same spot, nothing is drawn
+        out.clip_position = vec4(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+
+    let corners = array(
+        vec2(-1.0, -1.0),
+        vec2(1.0, -1.0),
+        vec2(1.0, 1.0),
+        vec2(-1.0, -1.0),
+        vec2(1.0, 1.0),
+        vec2(-1.0, 1.0)
+    );
+    let corner = corners[vertex_index];
+
+    let t = age / lifetime;
+    let size = mix(particle.params.x, particle.params.y, t);
+    let center = particle.position.xyz;
+    let to_camera = normalize(camera.view_pos.xyz - center);
+
+    var color = mix(particle.color_start, particle.color_end, t);
+    var world: vec3<f32>;
+    switch particle.kind.x {
+        case STREAK: {
+            let speed = length(particle.velocity.xyz);
+            let axis = select(vec3(0.0, 1.0, 0.0), particle.velocity.xyz / speed, speed > 1e-4);
+            let side = normalize(cross(axis, to_camera));
+            let half_length = size + speed * particle.motion.y;
+            world = center + axis * corner.y * half_length + side * corner.x * size;
+        }
+        case SHARD: {
+            // A triangle, the second half of the quad collapses
+            let points = array(
+                vec2(0.0, 1.0),
+                vec2(-0.87, -0.5),
+                vec2(0.87, -0.5)
+            );
+            let point = select(points[0], points[vertex_index % 3u], vertex_index < 3u);
+            let axis = particle.spin.xyz;
+            let angle = particle.motion.x;
+            world = center + rotate(vec3(point, 0.0) * size, axis, angle);
+
+            // Glints when it faces the camera
+            let normal = rotate(vec3(0.0, 0.0, 1.0), axis, angle);
+            color = vec4(color.rgb * (0.4 + abs(dot(normal, to_camera))), color.a);
+        }
+        default: {
+            let right = normalize(cross(vec3(0.0, 1.0, 0.0), to_camera));
+            let up = cross(to_camera, right);
+            let c = cos(particle.motion.x);
+            let s = sin(particle.motion.x);
+            let turned = vec2(corner.x * c - corner.y * s, corner.x * s + corner.y * c);
+            world = center + (right * turned.x + up * turned.y) * size;
+        }
+    }
+
+    out.clip_position = camera.view_proj * vec4(world, 1.0);
+    out.color = color;
+    out.uv = corner;
+    out.shape = particle.kind.x;
+    out.view_offset = world - camera.view_pos.xyz;
+
+    return out;
+}
+
+// How much of the quad the particle covers at this fragment
+fn coverage(in: VertexOutput) -> f32 {
+    switch in.shape {
+        case STREAK: {
+            return (1.0 - in.uv.x * in.uv.x) * (1.0 - smoothstep(0.5, 1.0, abs(in.uv.y)));
+        }
+        case SHARD: {
+            return 1.0;
+        }
+        default: {
+            return 1.0 - smoothstep(0.0, 1.0, length(in.uv));
+        }
+    }
+}
+
+@vertex
+fn vs_additive(@builtin(vertex_index) vertex_index: u32, particle: ParticleInput) -> VertexOutput {
+    return particle_vertex(vertex_index, particle, ADDITIVE);
+}
+
+@vertex
+fn vs_alpha(@builtin(vertex_index) vertex_index: u32, particle: ParticleInput) -> VertexOutput {
+    return particle_vertex(vertex_index, particle, ALPHA);
+}
+
+@fragment
+fn fs_additive(in: VertexOutput) -> @location(0) vec4<f32> {
+    let fog_value = fog_main(in.view_offset);
+    let light = in.color.rgb * in.color.a * coverage(in) * (1.0 - fog_value.factor);
+    return vec4(light, 0.0);
+}
+
+@fragment
+fn fs_alpha(in: VertexOutput) -> @location(0) vec4<f32> {
+    let fog_value = fog_main(in.view_offset);
+    let color = mix(in.color.rgb, fog_value.color.rgb, fog_value.factor);
+    return vec4(color, in.color.a * coverage(in));
+}
@@ -0,0 +1,49 @@
+struct Particle {
+    // xyz and the age in seconds
+    position: vec4<f32>,
+    // xyz and the lifetime in seconds
+    velocity: vec4<f32>,
+    color_start: vec4<f32>,
+    color_end: vec4<f32>,
+    // Start size, end size, drag and gravity
+    params: vec4<f32>,
+    // Spin axis and radians per second
+    spin: vec4<f32>,
+    shape: u32,
+    blend: u32,
+    rotation: f32,
+    stretch: f32,
+};
+
+struct Simulation {
+    gravity: vec3<f32>,
+    dt: f32,
+    count: u32,
+};
+
+@group(0) @binding(0) var<storage, read_write> particles: array<Particle>;
+@group(0) @binding(1) var<uniform> simulation: Simulation;
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= simulation.count {
+        return;
+    }
+
+    var particle = particles[id.x];
+    let age = particle.position.w;
+    let lifetime = particle.velocity.w;
+    if age >= lifetime {
+        return;
+    }
+
+    let dt = simulation.dt;
+    var velocity = particle.velocity.xyz + simulation.gravity * particle.params.w * dt;
+    velocity *= exp(-particle.params.z * dt);
+
+    particle.position = vec4(particle.position.xyz + velocity * dt, age + dt);
+    particle.velocity = vec4(velocity, lifetime);
+    particle.rotation += particle.spin.w * dt;
+
+    particles[id.x] = particle;
+}
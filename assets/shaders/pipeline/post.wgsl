@@ -0,0 +1,263 @@
+struct Post {
+    // rgb is the color, a the intensity
+    vignette: vec4<f32>,
+    exposure: f32,
+    tone_mapper: u32,
+    chromatic_aberration: f32,
+    grading_strength: f32,
+    grain_intensity: f32,
+    time: f32,
+    vignette_radius: f32,
+    vignette_smoothness: f32,
+    auto_exposure: u32,
+    exposure_key: f32,
+    min_exposure: f32,
+    max_exposure: f32,
+};
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+@group(0) @binding(2) var<uniform> post: Post;
+@group(0) @binding(3) var lut: texture_3d<f32>;
+@group(0) @binding(4) var lut_sampler: sampler;
+// Written by the luminance histogram pass
+@group(0) @binding(5) var<storage, read> average_luminance: f32;
+
+struct VertexOutput {
+    @location(0) uv: vec2<f32>,
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // Generate a triangle that covers the whole screen
+    out.uv = vec2<f32>(
+        f32((vi << 1u) & 2u),
+        f32(vi & 2u),
+    );
+    out.clip_position = vec4<f32>(out.uv * 2.0 - 1.0, 0.0, 1.0);
+    // We need to invert the y coordinate so the image
+    // is not upside down
+    out.uv.y = 1.0 - out.uv.y;
+    return out;
+}
+
+// Maps HDR values to linear values
+// Based on http://www.oscars.org/science-technology/sci-tech-projects/aces
+fn aces_tone_map(hdr: vec3<f32>) -> vec3<f32> {
+    let m1 = mat3x3(
+        0.59719, 0.07600, 0.02840,
+        0.35458, 0.90834, 0.13383,
+        0.04823, 0.01566, 0.83777,
+    );
+    let m2 = mat3x3(
+        1.60475, -0.10208, -0.00327,
+        -0.53108,  1.10813, -0.07276,
+        -0.07367, -0.00605,  1.07602,
+    );
+    let v = m1 * hdr;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(m2 * (a / b), vec3(0.0), vec3(1.0));
+}
+
+// Polynomial fit of the AgX base contrast curve
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x
+        + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+// Based on https://iolite-engine.com/blog_posts/minimal_agx_implementation
+fn agx_tone_map(hdr: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+    );
+    let outset = mat3x3(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var v = inset * hdr;
+    v = clamp(log2(max(v, vec3(1e-10))), vec3(min_ev), vec3(max_ev));
+    v = agx_contrast((v - min_ev) / (max_ev - min_ev));
+    v = outset * v;
+
+    // The curve outputs display encoded values
+    return clamp(pow(max(v, vec3(0.0)), vec3(2.2)), vec3(0.0), vec3(1.0));
+}
+
+fn reinhard_tone_map(hdr: vec3<f32>) -> vec3<f32> {
+    return hdr / (1.0 + hdr);
+}
+
+fn uncharted2_curve(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+// http://filmicworlds.com/blog/filmic-tonemapping-operators/
+fn uncharted2_tone_map(hdr: vec3<f32>) -> vec3<f32> {
+    let exposure_bias = 2.0;
+    let white_scale = 1.0 / uncharted2_curve(vec3(11.2));
+    return clamp(uncharted2_curve(hdr * exposure_bias) * white_scale, vec3(0.0), vec3(1.0));
+}
+
+// Matches the order of `ToneMapper`
+const TONE_MAPPER_NONE: u32 = 0u;
+const TONE_MAPPER_ACES: u32 = 1u;
+const TONE_MAPPER_AGX: u32 = 2u;
+const TONE_MAPPER_REINHARD: u32 = 3u;
+const TONE_MAPPER_UNCHARTED2: u32 = 4u;
+
+fn tone_map(hdr: vec3<f32>) -> vec3<f32> {
+    switch post.tone_mapper {
+        case TONE_MAPPER_ACES: {
+            return aces_tone_map(hdr);
+        }
+        case TONE_MAPPER_AGX: {
+            return agx_tone_map(hdr);
+        }
+        case TONE_MAPPER_REINHARD: {
+            return reinhard_tone_map(hdr);
+        }
+        case TONE_MAPPER_UNCHARTED2: {
+            return uncharted2_tone_map(hdr);
+        }
+        default: {
+            return clamp(hdr, vec3(0.0), vec3(1.0));
+        }
+    }
+}
+
+fn hash(p: vec2<f32>) -> f32 {
+    let q = fract(p * vec2(123.34, 456.21));
+    return fract(dot(q, q + 45.32) * (q.x + 45.32));
+}
+
+@fragment
+fn fs_exposure(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+
+    var exposure = post.exposure;
+    if post.auto_exposure != 0u {
+        exposure *= clamp(
+            post.exposure_key / max(average_luminance, 0.0001),
+            post.min_exposure,
+            post.max_exposure,
+        );
+    }
+
+    return vec4(color.rgb * exposure, color.a);
+}
+
+@fragment
+fn fs_chromatic_aberration(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Channels drift apart towards the screen edges
+    let offset = (in.uv - 0.5) * post.chromatic_aberration;
+    let color = textureSample(source, source_sampler, in.uv);
+    let r = textureSample(source, source_sampler, in.uv + offset).r;
+    let b = textureSample(source, source_sampler, in.uv - offset).b;
+    return vec4(r, color.g, b, color.a);
+}
+
+@fragment
+fn fs_tone_map(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    return vec4(tone_map(color.rgb), color.a);
+}
+
+@fragment
+fn fs_color_grading(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+
+    // LUTs are authored on gamma encoded images
+    let encoded = pow(clamp(color.rgb, vec3(0.0), vec3(1.0)), vec3(1.0 / 2.2));
+    let size = f32(textureDimensions(lut).x);
+    let uvw = encoded * (size - 1.0) / size + 0.5 / size;
+    let graded = pow(textureSample(lut, lut_sampler, uvw).rgb, vec3(2.2));
+
+    return vec4(mix(color.rgb, graded, post.grading_strength), color.a);
+}
+
+@fragment
+fn fs_vignette(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+
+    // 0 in the center, 1 in the corners
+    let distance = length(in.uv - 0.5) * 1.41421356;
+    let amount = smoothstep(
+        post.vignette_radius,
+        post.vignette_radius + post.vignette_smoothness,
+        distance,
+    ) * post.vignette.a;
+
+    return vec4(mix(color.rgb, post.vignette.rgb, amount), color.a);
+}
+
+@fragment
+fn fs_film_grain(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    let pixel = floor(in.uv * vec2<f32>(textureDimensions(source)));
+    let noise = hash(pixel + fract(post.time) * 1000.0) - 0.5;
+    return vec4(color.rgb + noise * post.grain_intensity, color.a);
+}
+
+@fragment
+fn fs_blit(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(source, source_sampler, in.uv);
+}
+
+// Catmull-Rom filtered sample in 9 bilinear taps
+// https://vec3.ca/bicubic-filtering-in-fewer-taps/
+fn sample_catmull_rom(uv: vec2<f32>) -> vec4<f32> {
+    let size = vec2<f32>(textureDimensions(source));
+    let position = uv * size;
+    let center = floor(position - 0.5) + 0.5;
+    let f = position - center;
+
+    let w0 = f * (-0.5 + f * (1.0 - 0.5 * f));
+    let w1 = 1.0 + f * f * (-2.5 + 1.5 * f);
+    let w2 = f * (0.5 + f * (2.0 - 1.5 * f));
+    let w3 = f * f * (-0.5 + 0.5 * f);
+
+    // The middle taps are merged into one bilinear sample
+    let w12 = w1 + w2;
+    let uv0 = (center - 1.0) / size;
+    let uv12 = (center + w2 / w12) / size;
+    let uv3 = (center + 2.0) / size;
+
+    var color = vec4(0.0);
+    color += textureSampleLevel(source, source_sampler, vec2(uv0.x, uv0.y), 0.0) * w0.x * w0.y;
+    color += textureSampleLevel(source, source_sampler, vec2(uv12.x, uv0.y), 0.0) * w12.x * w0.y;
+    color += textureSampleLevel(source, source_sampler, vec2(uv3.x, uv0.y), 0.0) * w3.x * w0.y;
+    color += textureSampleLevel(source, source_sampler, vec2(uv0.x, uv12.y), 0.0) * w0.x * w12.y;
+    color += textureSampleLevel(source, source_sampler, vec2(uv12.x, uv12.y), 0.0) * w12.x * w12.y;
+    color += textureSampleLevel(source, source_sampler, vec2(uv3.x, uv12.y), 0.0) * w3.x * w12.y;
+    color += textureSampleLevel(source, source_sampler, vec2(uv0.x, uv3.y), 0.0) * w0.x * w3.y;
+    color += textureSampleLevel(source, source_sampler, vec2(uv12.x, uv3.y), 0.0) * w12.x * w3.y;
+    color += textureSampleLevel(source, source_sampler, vec2(uv3.x, uv3.y), 0.0) * w3.x * w3.y;
+
+    // The negative lobes can overshoot below zero on hard edges
+    return max(color, vec4(0.0));
+}
+
+// Like `fs_blit`, for a scene rendered at a different resolution
+@fragment
+fn fs_upscale(in: VertexOutput) -> @location(0) vec4<f32> {
+    return sample_catmull_rom(in.uv);
+}
@@ -13,8 +13,16 @@ struct FogValue {
     factor: f32,
 }
 
-fn fog_main(screen_t: f32, depth: f32) -> FogValue {
-    let fog_color = mix(fog.lower_color, fog.upper_color, screen_t);
+// Fog looking along the world space direction `dir`, the lower color
+// straight down blending into the upper color straight up
+fn fog_color(dir: vec3<f32>) -> vec4<f32> {
+    return mix(fog.lower_color, fog.upper_color, dir.y * 0.5 + 0.5);
+}
+
+// Fog between the camera and a point `view_offset` away from it
+fn fog_main(view_offset: vec3<f32>) -> FogValue {
+    let depth = length(view_offset);
+    let fog_color = fog_color(view_offset / max(depth, 1e-6));
     
     let adjusted_depth = max(0.0, depth - fog.start);
     let density_factor = fog.density * adjusted_depth;
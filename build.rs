@@ -3,7 +3,7 @@ use std::fs;
 fn main() {
     println!("cargo:rerun-if-changed=assets/shaders");
 
-    for entry in fs::read_dir("assets/shaders").unwrap() {
+    for entry in fs::read_dir("assets/shaders/pipeline").unwrap() {
         let entry = entry.unwrap();
         if entry.file_type().unwrap().is_file() {
             let name_owned = entry.file_name();
@@ -0,0 +1,138 @@
+use glam::Vec3;
+use rapier3d::{
+    math::Vector,
+    prelude::{ColliderHandle, RigidBodyHandle},
+};
+
+use crate::physics::Physics;
+
+pub struct BallPoolSettings {
+    /// How many balls can be alive at the same time. When the pool is
+    /// exhausted the oldest ball is recycled.
+    pub max_count: usize,
+    /// Seconds after which a ball is despawned.
+    pub lifetime: f32,
+    /// Balls falling below this height are despawned.
+    pub kill_height: f32,
+    pub radius: f32,
+}
+
+impl Default for BallPoolSettings {
+    fn default() -> Self {
+        Self {
+            max_count: 64,
+            lifetime: 10.0,
+            kill_height: -50.0,
+            radius: 0.5,
+        }
+    }
+}
+
+pub struct Ball {
+    pub body: RigidBodyHandle,
+    pub collider: ColliderHandle,
+    /// Seconds since spawn, `None` while the ball is parked in the pool.
+    pub age: Option<f32>,
+}
+
+/// Fixed set of ball bodies that are enabled and disabled instead of
+/// being created and removed, so instance slots and rapier handles stay stable.
+pub struct BallPool {
+    pub settings: BallPoolSettings,
+    pub mesh_id: u64,
+    pub balls: Vec<Ball>,
+}
+
+impl BallPool {
+    pub fn new(physics: &mut Physics, mesh_id: u64, settings: BallPoolSettings) -> Self {
+        let balls = (0..settings.max_count)
+            .map(|slot| {
+                let id = Self::slot_id(mesh_id, slot);
+                let (body, collider) =
+                    physics.create_ball(id, Vec3::ZERO, Vec3::ZERO, settings.radius);
+                physics.bodies[body].set_enabled(false);
+
+                Ball {
+                    body,
+                    collider,
+                    age: None,
+                }
+            })
+            .collect();
+
+        Self {
+            settings,
+            mesh_id,
+            balls,
+        }
+    }
+
+    pub fn slot_id(mesh_id: u64, slot: usize) -> u128 {
+        ((mesh_id as u128) << 64) | (slot as u128)
+    }
+
+    pub fn active_count(&self) -> usize {
+        self.balls.iter().filter(|ball| ball.age.is_some()).count()
+    }
+
+    /// Activates a free slot, or recycles the oldest ball, and returns its index.
+    pub fn spawn(&mut self, physics: &mut Physics, position: Vec3, velocity: Vec3) -> usize {
+        let slot = self
+            .balls
+            .iter()
+            .position(|ball| ball.age.is_none())
+            .or_else(|| {
+                self.balls
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.age.unwrap_or(0.0).total_cmp(&b.age.unwrap_or(0.0)))
+                    .map(|(slot, _)| slot)
+            })
+            .expect("ball pool is empty");
+
+        let ball = &mut self.balls[slot];
+        ball.age = Some(0.0);
+
+        let body = &mut physics.bodies[ball.body];
+        body.set_enabled(true);
+        body.set_translation(Vector::new(position.x, position.y, position.z), true);
+        body.set_rotation(Default::default(), true);
+        body.set_linvel(Vector::new(velocity.x, velocity.y, velocity.z), true);
+        body.set_angvel(Vector::zeros(), true);
+
+        // Don't interpolate from wherever the slot was before
+        physics
+            .previous_positions
+            .insert(ball.body, *body.position());
+
+        slot
+    }
+
+    pub fn despawn(&mut self, physics: &mut Physics, slot: usize) {
+        let ball = &mut self.balls[slot];
+        ball.age = None;
+        physics.bodies[ball.body].set_enabled(false);
+    }
+
+    /// Ages the active balls and despawns the ones that expired or fell out
+    /// of the world. Returns the slots that were freed.
+    pub fn update(&mut self, physics: &mut Physics, dt: f32) -> Vec<usize> {
+        let mut expired = Vec::new();
+
+        for (slot, ball) in self.balls.iter_mut().enumerate() {
+            let Some(age) = &mut ball.age else { continue };
+            *age += dt;
+
+            let height = physics.bodies[ball.body].translation().y;
+            if *age >= self.settings.lifetime || height < self.settings.kill_height {
+                expired.push(slot);
+            }
+        }
+
+        for &slot in &expired {
+            self.despawn(physics, slot);
+        }
+
+        expired
+    }
+}
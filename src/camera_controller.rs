@@ -1,23 +1,24 @@
-use glam::Vec3;
-use winit::{
-    dpi::PhysicalPosition,
-    event::{ElementState, MouseScrollDelta},
-    keyboard::KeyCode,
-};
+use glam::{Vec2, Vec3};
+use winit::{dpi::PhysicalPosition, event::MouseScrollDelta};
 
 use std::f32::consts::FRAC_PI_2;
 use std::time::Duration;
 
-use crate::renderer::uniform::camera::Camera;
+use crate::{
+    input::{Action, Input},
+    renderer::uniform::camera::Camera,
+};
 
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
 pub struct CameraController {
     movement: [f32; 3],
     rotation: [f32; 2],
+    /// Yaw and pitch in radians from analog aiming, applied as is
+    turn: Vec2,
     scroll: f32,
-    speed: f32,
-    sensitivity: f32,
+    pub speed: f32,
+    pub sensitivity: f32,
 }
 
 impl Default for CameraController {
@@ -31,45 +32,18 @@ impl CameraController {
         Self {
             movement: [0.0; 3],
             rotation: [0.0; 2],
+            turn: Vec2::ZERO,
             scroll: 0.0,
             speed,
             sensitivity,
         }
     }
 
-    pub fn process_keyboard(&mut self, key: KeyCode, state: ElementState) -> bool {
-        let value = if state == ElementState::Pressed {
-            1.0
-        } else {
-            0.0
-        };
-        match key {
-            KeyCode::KeyW => {
-                self.movement[2] = value;
-                true
-            }
-            KeyCode::KeyS => {
-                self.movement[2] = -value;
-                true
-            }
-            KeyCode::KeyA => {
-                self.movement[0] = -value;
-                true
-            }
-            KeyCode::KeyD => {
-                self.movement[0] = value;
-                true
-            }
-            KeyCode::Space => {
-                self.movement[1] = value;
-                true
-            }
-            KeyCode::ShiftLeft => {
-                self.movement[1] = -value;
-                true
-            }
-            _ => false,
-        }
+    /// Takes the movement from the held move actions
+    pub fn process_input(&mut self, input: &Input) {
+        self.movement[0] = input.axis(Action::MoveRight, Action::MoveLeft);
+        self.movement[1] = input.axis(Action::MoveUp, Action::MoveDown);
+        self.movement[2] = input.axis(Action::MoveForward, Action::MoveBackward);
     }
 
     pub fn process_mouse(&mut self, delta: (f64, f64)) {
@@ -77,6 +51,11 @@ impl CameraController {
         self.rotation[1] = delta.1 as f32;
     }
 
+    /// Turns the camera by yaw and pitch in radians, for gamepad sticks
+    pub fn process_aim(&mut self, turn: Vec2) {
+        self.turn += turn;
+    }
+
     pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
         self.scroll = match delta {
             MouseScrollDelta::LineDelta(_, y) => -y * 2.0,
@@ -84,6 +63,11 @@ impl CameraController {
         };
     }
 
+    /// Units per second the camera is flying forward with
+    pub fn forward_speed(&self) -> f32 {
+        self.movement[2] * self.speed
+    }
+
     pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
         let dt = dt.as_secs_f32();
 
@@ -99,6 +83,8 @@ impl CameraController {
         // Rotate camera
         camera.yaw += self.rotation[0] * self.sensitivity * dt;
         camera.pitch -= self.rotation[1] * self.sensitivity * dt;
+        camera.yaw += self.turn.x;
+        camera.pitch += self.turn.y;
         camera.pitch = camera.pitch.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2);
 
         // Handle zoom
@@ -107,6 +93,7 @@ impl CameraController {
 
         // Reset rotation
         self.rotation = [0.0; 2];
+        self.turn = Vec2::ZERO;
 
         camera.update_uniform();
     }
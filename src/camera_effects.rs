@@ -0,0 +1,241 @@
+use glam::Vec3;
+
+use crate::renderer::uniform::camera::{Camera, CameraOffset};
+
+/// Gameplay events the camera and the particles react to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameEvent {
+    /// The player ran into an obstacle at `position`
+    PlayerHit {
+        position: Vec3,
+    },
+    BallThrown,
+    /// The camera got faster by this many units per second
+    SpeedUp(f32),
+    /// A ball ran into something, see [Impact](crate::physics::Impact)
+    BallImpact {
+        position: Vec3,
+        normal: Vec3,
+        speed: f32,
+    },
+    /// A glass pane at `position` shattered
+    GlassBroken {
+        position: Vec3,
+    },
+    /// The player reached the checkpoint at `position`
+    Checkpoint {
+        position: Vec3,
+    },
+}
+
+/// Tuning of the camera effects
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraEffectSettings {
+    /// Scales every effect, 0 turns them off
+    pub intensity: f32,
+    /// Trauma lost per second
+    pub trauma_decay: f32,
+    /// Camera offset at full trauma
+    pub max_offset: f32,
+    /// Yaw, pitch and roll in radians at full trauma
+    pub max_angle: f32,
+    /// How fast the shake moves, in noise cells per second
+    pub frequency: f32,
+    /// Degrees of FOV kick per unit per second of speed-up
+    pub fov_kick: f32,
+    pub max_fov_kick: f32,
+    /// Rate at which FOV kicks and roll tilts settle back, per second
+    pub recovery: f32,
+}
+
+impl Default for CameraEffectSettings {
+    fn default() -> Self {
+        Self {
+            intensity: 1.0,
+            trauma_decay: 1.2,
+            max_offset: 0.15,
+            max_angle: 0.05,
+            frequency: 15.0,
+            fov_kick: 1.5,
+            max_fov_kick: 10.0,
+            recovery: 4.0,
+        }
+    }
+}
+
+/// Trauma added by running into something
+const HIT_TRAUMA: f32 = 0.6;
+/// Roll in radians from running into something, away from the obstacle
+const HIT_ROLL: f32 = 0.08;
+const THROW_TRAUMA: f32 = 0.1;
+
+/// Shake, FOV kicks and roll tilts on top of what the [CameraController]
+/// does. The shake follows trauma, which events add and time takes away.
+///
+/// [CameraController]: crate::camera_controller::CameraController
+pub struct CameraEffects {
+    pub settings: CameraEffectSettings,
+    /// From 0 to 1, the shake grows with its square
+    trauma: f32,
+    /// Seconds the shake noise has advanced
+    time: f32,
+    /// Degrees
+    fov_kick: f32,
+    /// Radians
+    roll: f32,
+}
+
+impl Default for CameraEffects {
+    fn default() -> Self {
+        Self::new(CameraEffectSettings::default())
+    }
+}
+
+impl CameraEffects {
+    pub fn new(settings: CameraEffectSettings) -> Self {
+        Self {
+            settings,
+            trauma: 0.0,
+            time: 0.0,
+            fov_kick: 0.0,
+            roll: 0.0,
+        }
+    }
+
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).clamp(0.0, 1.0);
+    }
+
+    /// Widens the field of view by `degrees`, it settles back over time
+    pub fn kick_fov(&mut self, degrees: f32) {
+        let max = self.settings.max_fov_kick;
+        self.fov_kick = (self.fov_kick + degrees).clamp(-max, max);
+    }
+
+    /// Rolls the camera by `radians`, it settles back over time
+    pub fn tilt(&mut self, radians: f32) {
+        self.roll = (self.roll + radians).clamp(-0.5, 0.5);
+    }
+
+    pub fn handle(&mut self, event: GameEvent, camera: &Camera) {
+        match event {
+            GameEvent::PlayerHit { position } => {
+                self.add_trauma(HIT_TRAUMA);
+
+                // Lean away from whatever was hit
+                let forward = camera.calc_view_dir();
+                let right = forward.cross(Vec3::Y).normalize_or_zero();
+                let side = (position - camera.position).dot(right);
+                self.tilt(if side > 0.0 { -HIT_ROLL } else { HIT_ROLL });
+            }
+            GameEvent::BallThrown => self.add_trauma(THROW_TRAUMA),
+            GameEvent::SpeedUp(amount) => self.kick_fov(amount * self.settings.fov_kick),
+            GameEvent::BallImpact { .. }
+            | GameEvent::GlassBroken { .. }
+            | GameEvent::Checkpoint { .. } => {}
+        }
+    }
+
+    /// Advances the effects and puts them on the camera. Call before
+    /// [Camera::update_uniform].
+    pub fn update(&mut self, dt: f32, camera: &mut Camera) {
+        self.advance(dt);
+        camera.offset = self.offset();
+    }
+
+    fn advance(&mut self, dt: f32) {
+        self.time += dt;
+        self.trauma = (self.trauma - self.settings.trauma_decay * dt).max(0.0);
+
+        let recovery = (-self.settings.recovery * dt).exp();
+        self.fov_kick *= recovery;
+        self.roll *= recovery;
+    }
+
+    pub fn offset(&self) -> CameraOffset {
+        let settings = &self.settings;
+        let shake = self.trauma * self.trauma * settings.intensity;
+        let t = self.time * settings.frequency;
+
+        CameraOffset {
+            position: Vec3::new(noise(0, t), noise(1, t), noise(2, t))
+                * settings.max_offset
+                * shake,
+            yaw: noise(3, t) * settings.max_angle * shake,
+            pitch: noise(4, t) * settings.max_angle * shake,
+            roll: self.roll * settings.intensity + noise(5, t) * settings.max_angle * shake,
+            fovy: self.fov_kick * settings.intensity,
+        }
+    }
+}
+
+/// Smooth 1D value noise from -1 to 1, `seed` picks an unrelated curve
+fn noise(seed: u32, t: f32) -> f32 {
+    let cell = t.floor();
+    let f = t - cell;
+    let a = lattice(seed, cell as i32);
+    let b = lattice(seed, cell as i32 + 1);
+
+    // Smoothstep, so the curve has no kinks at the lattice points
+    a + (b - a) * f * f * (3.0 - 2.0 * f)
+}
+
+fn lattice(seed: u32, x: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x9E37_79B9) ^ seed.wrapping_mul(0x85EB_CA6B);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2C1B_3C6D);
+    h ^= h >> 12;
+    h as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_is_bounded_and_smooth() {
+        for seed in 0..6 {
+            let mut previous = noise(seed, 0.0);
+            for step in 1..1000 {
+                let value = noise(seed, step as f32 * 0.01);
+                assert!((-1.0..=1.0).contains(&value));
+                assert!((value - previous).abs() < 0.05);
+                previous = value;
+            }
+        }
+    }
+
+    #[test]
+    fn trauma_fades_out() {
+        let mut effects = CameraEffects::default();
+        assert_eq!(effects.offset(), CameraOffset::default());
+
+        effects.add_trauma(HIT_TRAUMA);
+        effects.kick_fov(5.0);
+        effects.time = 0.37;
+        let offset = effects.offset();
+        assert_ne!(offset.position, Vec3::ZERO);
+        assert_eq!(offset.fovy, 5.0);
+
+        for _ in 0..60 {
+            effects.advance(1.0 / 60.0);
+        }
+        let offset = effects.offset();
+        assert_eq!(offset.position, Vec3::ZERO);
+        assert!(offset.fovy > 0.0 && offset.fovy < 0.1);
+    }
+
+    #[test]
+    fn intensity_turns_effects_off() {
+        let mut effects = CameraEffects::new(CameraEffectSettings {
+            intensity: 0.0,
+            ..Default::default()
+        });
+        effects.add_trauma(1.0);
+        effects.kick_fov(5.0);
+        effects.tilt(0.2);
+        effects.time = 0.5;
+
+        assert_eq!(effects.offset(), CameraOffset::default());
+    }
+}
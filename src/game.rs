@@ -1,31 +1,139 @@
-use std::{sync::Arc, time::Instant};
+use glam::Vec2;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use winit::{
     application::ApplicationHandler,
-    event::{DeviceEvent, MouseButton, WindowEvent},
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{DeviceEvent, WindowEvent},
+    event_loop::ControlFlow,
     keyboard::PhysicalKey,
     window::WindowAttributes,
 };
 
-use crate::scene::Scene;
+use crate::{
+    gamepad::Gamepads,
+    input::{Action, Binding, Input, InputEvent},
+    level::DEFAULT_LEVEL,
+    physics::Timestep,
+    replay::Recorder,
+    scene::Scene,
+    settings::{Settings, SettingsFile},
+    touch::{Touches, aim_assist},
+};
+
+/// Frame rate of F10 frame sequences
+const SEQUENCE_FPS: f32 = 60.0;
+/// World units the orthographic projection shows vertically
+const ORTHOGRAPHIC_HEIGHT: f32 = 40.0;
+/// How often the settings file is checked for edits
+const SETTINGS_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct Game {
     scene: Option<Scene>,
     last_update: Instant,
     last_frame: Instant,
-    target_fps: f32,
-    target_physics_ps: f32,
-    mouse_left: bool,
+    timestep: Timestep,
+    input: Input,
+    /// Cursor on the window, `None` while it's outside or the gamepad aims
+    pointer: Option<PhysicalPosition<f64>>,
+    touches: Touches,
+    gamepads: Gamepads,
+    paused: bool,
+    recorder: Option<Recorder>,
+    settings: SettingsFile,
+    last_settings_poll: Instant,
 }
 
 impl Default for Game {
     fn default() -> Self {
+        let settings = SettingsFile::open(Settings::default_path());
         Self {
             scene: None,
             last_update: Instant::now(),
             last_frame: Instant::now(),
-            target_fps: 1.0 / 60.0,
-            target_physics_ps: 1.0 / 32.0,
-            mouse_left: false,
+            timestep: Timestep::default(),
+            input: Input::new(settings.settings.bindings.clone()),
+            pointer: None,
+            touches: Touches::default(),
+            gamepads: Gamepads::new(settings.settings.gamepad.clone()),
+            paused: false,
+            recorder: None,
+            settings,
+            last_settings_poll: Instant::now(),
+        }
+    }
+}
+
+impl Game {
+    /// Records the session to `path`, it's saved when the window is closed
+    pub fn record_to(&mut self, path: &str) {
+        self.recorder = Some(Recorder::new(path, DEFAULT_LEVEL, self.timestep.tick_rate));
+    }
+
+    /// Throws a ball through a point on the window, or the screen center
+    fn throw(&mut self, pointer: Option<PhysicalPosition<f64>>) {
+        let Some(scene) = &mut self.scene else { return };
+        if self.paused {
+            return;
+        }
+
+        let ray = scene.pointer_ray(pointer);
+        if scene
+            .spawn_ball_instance(ray.origin, ray.direction, 15.0)
+            .is_some()
+            && let Some(recorder) = &mut self.recorder
+        {
+            recorder.record_throw(ray.origin, ray.direction, 15.0);
+        }
+    }
+
+    fn handle_input(&mut self, events: Vec<InputEvent>) {
+        for event in events {
+            if event == InputEvent::Pressed(Action::Throw) {
+                self.throw(self.pointer);
+                continue;
+            }
+
+            let Some(scene) = &mut self.scene else { return };
+            match event {
+                InputEvent::Pressed(Action::Pause) => {
+                    self.paused = !self.paused;
+                    log::info!("{}", if self.paused { "Paused" } else { "Resumed" });
+                }
+                InputEvent::Pressed(Action::ToggleDebugCamera) => {
+                    scene.debug_camera = !scene.debug_camera;
+                    log::info!(
+                        "Debug camera {}",
+                        if scene.debug_camera { "on" } else { "off" }
+                    );
+                }
+                InputEvent::Pressed(Action::ToggleOrthographic) => {
+                    let camera = &mut scene.renderer.uniforms.camera;
+                    let orthographic = &mut camera.projection.orthographic;
+                    *orthographic = match orthographic {
+                        Some(_) => None,
+                        None => Some(ORTHOGRAPHIC_HEIGHT),
+                    };
+                    log::info!(
+                        "Orthographic projection {}",
+                        if orthographic.is_some() { "on" } else { "off" }
+                    );
+                    camera.update_uniform();
+                }
+                InputEvent::Pressed(Action::Screenshot) => {
+                    scene.renderer.capture.request_screenshot();
+                }
+                InputEvent::Pressed(Action::RecordSequence) => {
+                    scene.renderer.capture.toggle_sequence(SEQUENCE_FPS);
+                }
+                InputEvent::Rebound(..) => {
+                    self.settings.settings.bindings = self.input.bindings.clone();
+                    self.settings.save();
+                }
+                _ => {}
+            }
         }
     }
 }
@@ -34,14 +142,20 @@ impl ApplicationHandler for Game {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         log::info!("Game resumed!");
 
+        let settings = &self.settings.settings;
         let window = Arc::new(
             event_loop
-                .create_window(WindowAttributes::default())
+                .create_window(
+                    WindowAttributes::default().with_inner_size(PhysicalSize::new(
+                        settings.window.width,
+                        settings.window.height,
+                    )),
+                )
                 .unwrap(),
         );
-        let mut scene = Scene::new(window);
+        let mut scene = Scene::new(window, settings);
 
-        scene.init_level();
+        scene.init_level(DEFAULT_LEVEL).unwrap();
 
         self.scene = Some(scene);
     }
@@ -55,25 +169,47 @@ impl ApplicationHandler for Game {
         if let Some(scene) = &mut self.scene {
             match event {
                 WindowEvent::RedrawRequested => {
-                    let now = Instant::now();
-                    let dt = now - self.last_frame;
-
-                    if dt.as_secs_f32() >= self.target_fps {
-                        scene.renderer.render().unwrap();
-                        self.last_frame = now;
-                    }
+                    scene.renderer.render().unwrap();
                 }
                 WindowEvent::Resized(new_size) => {
                     scene.renderer.resize(&new_size);
+
+                    let window = &mut self.settings.settings.window;
+                    (window.width, window.height) = (new_size.width, new_size.height);
                 }
                 WindowEvent::KeyboardInput { event, .. } => {
-                    if let PhysicalKey::Code(keycode) = event.physical_key {
-                        scene
-                            .camera_controller
-                            .process_keyboard(keycode, event.state);
+                    if let PhysicalKey::Code(keycode) = event.physical_key
+                        && !event.repeat
+                    {
+                        let events = self
+                            .input
+                            .handle(Binding::Key(keycode), event.state.is_pressed());
+                        self.handle_input(events);
+                    }
+                }
+                WindowEvent::CursorMoved { position, .. } => {
+                    self.pointer = Some(position);
+                }
+                WindowEvent::CursorLeft { .. } => {
+                    self.pointer = None;
+                }
+                WindowEvent::Touch(touch) => {
+                    if let Some(position) = self.touches.handle(&touch) {
+                        self.throw(Some(position));
                     }
                 }
+                WindowEvent::Focused(false) => {
+                    self.input.clear();
+                    self.touches.clear();
+                }
                 WindowEvent::CloseRequested => {
+                    if let Some(recorder) = self.recorder.take()
+                        && let Err(e) = recorder.finish(&scene.world)
+                    {
+                        log::error!("Failed to save recording: {e}");
+                    }
+                    self.settings.save();
+
                     log::info!("Dropping renderer");
                     // We need drop scene or else we get SIGSEGV
                     self.scene = None;
@@ -82,18 +218,10 @@ impl ApplicationHandler for Game {
                     event_loop.exit();
                 }
                 WindowEvent::MouseInput { state, button, .. } => {
-                    if MouseButton::Left == button {
-                        self.mouse_left = state.is_pressed();
-                    }
-
-                    if MouseButton::Left == button && state.is_pressed() {
-                        scene.spawn_ball_instance(
-                            scene.renderer.uniforms.camera.position,
-                            scene.renderer.uniforms.camera.calc_view_dir(),
-                            15.0,
-                            0.5,
-                        );
-                    }
+                    let events = self
+                        .input
+                        .handle(Binding::Mouse(button), state.is_pressed());
+                    self.handle_input(events);
                 }
                 _ => {}
             }
@@ -106,30 +234,125 @@ impl ApplicationHandler for Game {
         _device_id: winit::event::DeviceId,
         event: winit::event::DeviceEvent,
     ) {
-        if let DeviceEvent::MouseMotion { delta } = event {
-            if self.mouse_left {
-                if let Some(scene) = &mut self.scene {
-                    scene.camera_controller.process_mouse(delta);
-                }
-            }
+        if let DeviceEvent::MouseMotion { delta } = event
+            && self.input.is_held(Action::Aim)
+            && !self.input.is_rebinding()
+            && !self.paused
+            && let Some(scene) = &mut self.scene
+        {
+            scene.camera_controller.process_mouse(delta);
         }
     }
 
-    fn about_to_wait(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+    fn about_to_wait(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         if let Some(scene) = &mut self.scene {
             let now = Instant::now();
-            let dt = now - self.last_update;
+
+            // Sleep until the next frame is due under the frame cap.
+            // Sequences need every simulated frame rendered, they aren't capped.
+            if let Some(frame_time) = scene.renderer.settings().frame_time()
+                && scene.renderer.capture.sequence.is_none()
+            {
+                let next_frame = self.last_frame + Duration::from_secs_f32(frame_time);
+                if now < next_frame {
+                    event_loop.set_control_flow(ControlFlow::WaitUntil(next_frame));
+                    return;
+                }
+                // Keeps a steady pace, unless a long frame left it behind
+                self.last_frame = if now - next_frame < Duration::from_secs_f32(frame_time) {
+                    next_frame
+                } else {
+                    now
+                };
+            } else {
+                self.last_frame = now;
+            }
+            event_loop.set_control_flow(ControlFlow::Wait);
+
+            if now - self.last_settings_poll >= SETTINGS_POLL_INTERVAL {
+                self.last_settings_poll = now;
+
+                if let Some(settings) = self.settings.poll() {
+                    scene.apply_settings(settings);
+                    self.input.bindings = settings.bindings.clone();
+                    self.gamepads.settings = settings.gamepad.clone();
+
+                    let size = PhysicalSize::new(settings.window.width, settings.window.height);
+                    if let Some(window) = scene.renderer.window()
+                        && window.inner_size() != size
+                    {
+                        let _ = window.request_inner_size(size);
+                    }
+                }
+            }
+
+            let events = self.gamepads.poll(&mut self.input);
+            self.handle_input(events);
+            let Some(scene) = &mut self.scene else { return };
+
+            let mut dt = now - self.last_update;
             self.last_update = now;
 
+            // Frame sequences advance by a fixed step, however long a frame takes
+            if let Some(frame_time) = scene.renderer.capture.sequence_frame_time() {
+                dt = Duration::from_secs_f32(frame_time);
+            }
+
+            scene
+                .renderer
+                .pipelines
+                .post_pipeline
+                .advance(dt.as_secs_f32());
+
+            if self.paused {
+                if let Some(window) = scene.renderer.window() {
+                    window.request_redraw();
+                }
+                return;
+            }
+
+            scene
+                .renderer
+                .pipelines
+                .particle_pipeline
+                .advance(dt.as_secs_f32());
+            scene.camera_controller.process_input(&self.input);
+            let mut turn = self.gamepads.aim(dt.as_secs_f32());
+            if self.input.is_rebinding() {
+                // Sticks don't look around while picking a binding
+                turn = Vec2::ZERO;
+            }
+            if turn != Vec2::ZERO {
+                // Throws go to the screen center while the stick aims
+                self.pointer = None;
+            }
+            scene.camera_controller.process_aim(turn);
+
+            if let Some(direction) = self.touches.update(dt.as_secs_f32(), |finger| {
+                scene.pointer_ray(Some(finger)).direction
+            }) {
+                let camera = &scene.renderer.uniforms.camera;
+                let turn = aim_assist(direction, camera.yaw, camera.pitch, dt.as_secs_f32());
+                scene.camera_controller.process_aim(turn);
+            }
             scene
                 .camera_controller
                 .update_camera(&mut scene.renderer.uniforms.camera, dt);
-            scene
-                .physics
-                .step(dt.as_secs_f32(), self.target_physics_ps, 1.0, 1);
-            scene.update_objects();
-            scene.cull_instances_behind_camera();
-            scene.renderer.window.request_redraw();
+
+            for _ in 0..self.timestep.advance(dt.as_secs_f32()) {
+                // Before the tick, it may push the camera back from obstacles
+                let camera = scene.renderer.uniforms.camera.state();
+                scene.tick(&self.timestep);
+
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.record_tick(camera, scene.debug_camera);
+                }
+            }
+            scene.update_effects(dt.as_secs_f32());
+            scene.update_objects(self.timestep.alpha());
+            if let Some(window) = scene.renderer.window() {
+                window.request_redraw();
+            }
         }
     }
 }
@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::input::{Action, Binding, GamepadButton, Input, InputEvent};
+
+/// Stick value past which the aim speeds up
+const ACCELERATION_EDGE: f32 = 0.95;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GamepadSettings {
+    /// Stick travel that is ignored, from the center
+    pub deadzone: f32,
+    /// Stick travel that already counts as fully pushed, from the edge
+    pub outer_deadzone: f32,
+    /// Exponent of the aim response curve, above 1 gives finer control
+    /// near the center
+    pub aim_curve: f32,
+    /// Turn rate in radians per second at full deflection
+    pub aim_speed: f32,
+    /// Turn rate multiplier reached after holding the stick at the edge
+    pub aim_acceleration: f32,
+    /// Seconds at the edge until `aim_acceleration` is reached
+    pub acceleration_time: f32,
+    pub invert_y: bool,
+    /// Trigger travel that presses it
+    pub trigger_threshold: f32,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self {
+            deadzone: 0.15,
+            outer_deadzone: 0.05,
+            aim_curve: 2.0,
+            aim_speed: 2.5,
+            aim_acceleration: 1.8,
+            acceleration_time: 0.4,
+            invert_y: false,
+            trigger_threshold: 0.5,
+        }
+    }
+}
+
+impl GamepadSettings {
+    /// Rescales the stick so the usable travel between the dead zones maps
+    /// to 0..1. Radial, so diagonals aren't snapped to the axes.
+    pub fn apply_deadzone(&self, stick: Vec2) -> Vec2 {
+        let length = stick.length();
+        if length <= self.deadzone {
+            return Vec2::ZERO;
+        }
+
+        let live = (1.0 - self.outer_deadzone - self.deadzone).max(f32::EPSILON);
+        let scaled = ((length - self.deadzone) / live).min(1.0);
+        stick / length * scaled
+    }
+
+    /// Applies the response curve to a stick past the dead zone
+    pub fn aim_response(&self, stick: Vec2) -> Vec2 {
+        let length = stick.length();
+        if length == 0.0 {
+            return Vec2::ZERO;
+        }
+        stick / length * length.powf(self.aim_curve)
+    }
+}
+
+/// Reads gamepads through gilrs and feeds them into the [Input] action
+/// layer. Follows whichever pad was used last, so pads can be swapped and
+/// plugged in or out at any time.
+pub struct Gamepads {
+    pub settings: GamepadSettings,
+    /// `None` when the platform has no gamepad support
+    gilrs: Option<Gilrs>,
+    active: Option<GamepadId>,
+    /// Triggers pressed past `trigger_threshold`
+    triggers: HashSet<Button>,
+    /// Seconds the aim stick has been held at the edge
+    edge_time: f32,
+}
+
+impl Gamepads {
+    pub fn new(settings: GamepadSettings) -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                log::warn!("Gamepads unavailable: {e}");
+                None
+            }
+        };
+
+        let active = gilrs.as_ref().and_then(|gilrs| {
+            gilrs.gamepads().next().map(|(id, gamepad)| {
+                log::info!("Using gamepad {}", gamepad.name());
+                id
+            })
+        });
+
+        Self {
+            settings,
+            gilrs,
+            active,
+            triggers: HashSet::new(),
+            edge_time: 0.0,
+        }
+    }
+
+    /// Handles the pending gamepad events, returns the actions they
+    /// triggered. Stick movement goes into analog action values.
+    pub fn poll(&mut self, input: &mut Input) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        let Some(gilrs) = &mut self.gilrs else {
+            return events;
+        };
+
+        while let Some(event) = gilrs.next_event() {
+            match event.event {
+                EventType::Connected => {
+                    let name = gilrs.gamepad(event.id).name().to_owned();
+                    log::info!("Gamepad connected: {name}");
+                    self.active.get_or_insert(event.id);
+                }
+                EventType::Disconnected => {
+                    log::info!("Gamepad disconnected: {}", gilrs.gamepad(event.id).name());
+                    if self.active == Some(event.id) {
+                        events.extend(Self::release_all(input, &mut self.triggers));
+                        self.active = gilrs.gamepads().next().map(|(id, _)| id);
+                    }
+                }
+                _ if self.active != Some(event.id) => {
+                    // Pressing something on another pad switches to it
+                    if let EventType::ButtonPressed(button, _) = event.event {
+                        log::info!("Switching to gamepad {}", gilrs.gamepad(event.id).name());
+                        events.extend(Self::release_all(input, &mut self.triggers));
+                        self.active = Some(event.id);
+                        events.extend(press(input, button, true));
+                    }
+                }
+                EventType::ButtonPressed(button, _) if !is_trigger(button) => {
+                    events.extend(press(input, button, true));
+                }
+                EventType::ButtonReleased(button, _) if !is_trigger(button) => {
+                    events.extend(press(input, button, false));
+                }
+                EventType::ButtonChanged(button, value, _) if is_trigger(button) => {
+                    let pressed = value >= self.settings.trigger_threshold;
+                    let changed = if pressed {
+                        self.triggers.insert(button)
+                    } else {
+                        self.triggers.remove(&button)
+                    };
+                    if changed {
+                        events.extend(press(input, button, pressed));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let movement = self
+            .settings
+            .apply_deadzone(self.stick(Axis::LeftStickX, Axis::LeftStickY));
+        input.set_analog(Action::MoveForward, movement.y);
+        input.set_analog(Action::MoveBackward, -movement.y);
+        input.set_analog(Action::MoveRight, movement.x);
+        input.set_analog(Action::MoveLeft, -movement.x);
+
+        events
+    }
+
+    fn release_all(input: &mut Input, triggers: &mut HashSet<Button>) -> Vec<InputEvent> {
+        triggers.clear();
+        GamepadButton::ALL
+            .into_iter()
+            .flat_map(|button| input.handle(Binding::Gamepad(button), false))
+            .collect()
+    }
+
+    fn stick(&self, x: Axis, y: Axis) -> Vec2 {
+        let gamepad = self
+            .gilrs
+            .as_ref()
+            .zip(self.active)
+            .and_then(|(gilrs, id)| gilrs.connected_gamepad(id));
+
+        match gamepad {
+            Some(gamepad) => Vec2::new(gamepad.value(x), gamepad.value(y)),
+            None => Vec2::ZERO,
+        }
+    }
+
+    /// Camera turn from the right stick for this frame, in radians as
+    /// (yaw, pitch)
+    pub fn aim(&mut self, dt: f32) -> Vec2 {
+        let settings = &self.settings;
+        let stick = settings.apply_deadzone(self.stick(Axis::RightStickX, Axis::RightStickY));
+
+        // Holding the stick at the edge means the player wants to turn
+        // further than the base speed allows
+        if stick.length() >= ACCELERATION_EDGE {
+            self.edge_time += dt;
+        } else {
+            self.edge_time = 0.0;
+        }
+        let ramp = (self.edge_time / settings.acceleration_time.max(f32::EPSILON)).min(1.0);
+        let acceleration = 1.0 + (settings.aim_acceleration - 1.0) * ramp;
+
+        let mut turn = settings.aim_response(stick) * settings.aim_speed * acceleration * dt;
+        // Stick up is positive, pitching up is too
+        if settings.invert_y {
+            turn.y = -turn.y;
+        }
+        turn
+    }
+}
+
+fn press(input: &mut Input, button: Button, pressed: bool) -> Vec<InputEvent> {
+    match map_button(button) {
+        Some(button) => input.handle(Binding::Gamepad(button), pressed),
+        None => Vec::new(),
+    }
+}
+
+fn is_trigger(button: Button) -> bool {
+    matches!(button, Button::LeftTrigger2 | Button::RightTrigger2)
+}
+
+fn map_button(button: Button) -> Option<GamepadButton> {
+    Some(match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::North => GamepadButton::North,
+        Button::West => GamepadButton::West,
+        Button::LeftTrigger => GamepadButton::LeftBumper,
+        Button::RightTrigger => GamepadButton::RightBumper,
+        Button::LeftTrigger2 => GamepadButton::LeftTrigger,
+        Button::RightTrigger2 => GamepadButton::RightTrigger,
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftStick,
+        Button::RightThumb => GamepadButton::RightStick,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadzone_is_radial() {
+        let settings = GamepadSettings::default();
+
+        assert_eq!(settings.apply_deadzone(Vec2::new(0.1, 0.1)), Vec2::ZERO);
+        assert_eq!(settings.apply_deadzone(Vec2::new(0.0, 0.97)), Vec2::Y);
+
+        let diagonal = settings.apply_deadzone(Vec2::new(0.5, 0.5));
+        assert!((diagonal.x - diagonal.y).abs() < 1e-6);
+        assert!(diagonal.length() < 1.0);
+    }
+
+    #[test]
+    fn response_curve_keeps_direction() {
+        let settings = GamepadSettings::default();
+        let response = settings.aim_response(Vec2::new(0.5, 0.0));
+
+        assert_eq!(response, Vec2::new(0.25, 0.0));
+        assert_eq!(settings.aim_response(Vec2::X), Vec2::X);
+    }
+}
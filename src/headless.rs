@@ -0,0 +1,140 @@
+use std::fs;
+
+use anyhow::{Context, Result, bail};
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    level::{DEFAULT_LEVEL, Level},
+    physics::Timestep,
+    world::World,
+};
+
+/// A throw performed at a given tick of a headless run
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ScriptedThrow {
+    pub tick: u32,
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub speed: f32,
+}
+
+/// Simulates a level without a window or GPU:
+/// `smashbit --headless [--level <path>] [--ticks <n>] [--script <path>]`
+pub struct Headless {
+    pub level: String,
+    pub ticks: u32,
+    pub throws: Vec<ScriptedThrow>,
+    pub timestep: Timestep,
+}
+
+impl Default for Headless {
+    fn default() -> Self {
+        let timestep = Timestep::default();
+
+        // One throw a second from the default camera pose
+        let throws = (0..10)
+            .map(|i| ScriptedThrow {
+                tick: i * timestep.tick_rate as u32,
+                position: Vec3::new(0.0, 1.0, 2.0),
+                direction: Vec3::NEG_Z,
+                speed: 15.0,
+            })
+            .collect();
+
+        Self {
+            level: DEFAULT_LEVEL.to_string(),
+            ticks: 600,
+            throws,
+            timestep,
+        }
+    }
+}
+
+impl Headless {
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let mut headless = Self::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => {}
+                "--level" => headless.level = args.next().context("missing level path")?.clone(),
+                "--ticks" => headless.ticks = args.next().context("missing tick count")?.parse()?,
+                "--script" => {
+                    let path = args.next().context("missing script path")?;
+                    headless.throws = ron::from_str(&fs::read_to_string(path)?)?;
+                }
+                _ => bail!("unknown argument {arg}"),
+            }
+        }
+
+        Ok(headless)
+    }
+
+    pub fn run(&self) -> Result<World> {
+        let mut world = World::new();
+        world.load_level(&Level::load(&self.level)?)?;
+
+        log::info!("Simulating {} ticks", self.ticks);
+        for tick in 0..self.ticks {
+            for throw in self.throws.iter().filter(|throw| throw.tick == tick) {
+                world.throw_ball(throw.position, throw.direction.normalize(), throw.speed);
+            }
+
+            world.tick(self.timestep.dt());
+        }
+
+        log::info!("Done, {} balls alive", world.balls.active_count());
+        for (slot, ball) in world.balls.balls.iter().enumerate() {
+            if ball.age.is_some() {
+                let position = world.physics.bodies[ball.body].translation();
+                log::info!(
+                    "Ball {slot} at {}",
+                    Vec3::new(position.x, position.y, position.z)
+                );
+            }
+        }
+
+        Ok(world)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_level_runs_headless() {
+        let headless = Headless {
+            ticks: 120,
+            ..Default::default()
+        };
+        let world = headless.run().unwrap();
+
+        assert!(world.balls.active_count() > 0);
+        assert!(world.physics.bodies.len() > world.balls.balls.len());
+    }
+
+    #[test]
+    fn headless_runs_are_deterministic() {
+        let headless = Headless {
+            ticks: 240,
+            ..Default::default()
+        };
+
+        let positions = |world: World| -> Vec<_> {
+            world
+                .balls
+                .balls
+                .iter()
+                .map(|ball| *world.physics.bodies[ball.body].position())
+                .collect()
+        };
+
+        assert_eq!(
+            positions(headless.run().unwrap()),
+            positions(headless.run().unwrap())
+        );
+    }
+}
@@ -0,0 +1,428 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use winit::{event::MouseButton, keyboard::KeyCode};
+
+/// What the player wants to do, independent of the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    Throw,
+    /// Mouse movement turns the camera while held
+    Aim,
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Pause,
+    /// Free flying camera for looking around levels
+    ToggleDebugCamera,
+    /// Orthographic projection for looking over whole levels
+    ToggleOrthographic,
+    Screenshot,
+    RecordSequence,
+    /// The next pressed input picks the action to rebind, pressing it again
+    /// cycles through the other actions it triggers. A different input then
+    /// becomes the new binding.
+    Rebind,
+}
+
+/// Gamepad controls, named after their position on the pad
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl GamepadButton {
+    pub const ALL: [Self; 16] = [
+        Self::South,
+        Self::East,
+        Self::North,
+        Self::West,
+        Self::LeftBumper,
+        Self::RightBumper,
+        Self::LeftTrigger,
+        Self::RightTrigger,
+        Self::Select,
+        Self::Start,
+        Self::LeftStick,
+        Self::RightStick,
+        Self::DPadUp,
+        Self::DPadDown,
+        Self::DPadLeft,
+        Self::DPadRight,
+    ];
+}
+
+/// A physical input an action can be bound to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+/// Which inputs trigger which action. An input can trigger several actions.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Bindings(pub BTreeMap<Action, Vec<Binding>>);
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Binding::{Gamepad, Key, Mouse};
+
+        Self(BTreeMap::from([
+            (
+                Action::Throw,
+                vec![
+                    Mouse(MouseButton::Left),
+                    Gamepad(GamepadButton::RightTrigger),
+                ],
+            ),
+            (Action::Aim, vec![Mouse(MouseButton::Left)]),
+            (
+                Action::MoveForward,
+                vec![Key(KeyCode::KeyW), Gamepad(GamepadButton::DPadUp)],
+            ),
+            (
+                Action::MoveBackward,
+                vec![Key(KeyCode::KeyS), Gamepad(GamepadButton::DPadDown)],
+            ),
+            (
+                Action::MoveLeft,
+                vec![Key(KeyCode::KeyA), Gamepad(GamepadButton::DPadLeft)],
+            ),
+            (
+                Action::MoveRight,
+                vec![Key(KeyCode::KeyD), Gamepad(GamepadButton::DPadRight)],
+            ),
+            (
+                Action::MoveUp,
+                vec![Key(KeyCode::Space), Gamepad(GamepadButton::RightBumper)],
+            ),
+            (
+                Action::MoveDown,
+                vec![Key(KeyCode::ShiftLeft), Gamepad(GamepadButton::LeftBumper)],
+            ),
+            (
+                Action::Pause,
+                vec![Key(KeyCode::Escape), Gamepad(GamepadButton::Start)],
+            ),
+            (
+                Action::ToggleDebugCamera,
+                vec![Key(KeyCode::F1), Gamepad(GamepadButton::Select)],
+            ),
+            (Action::ToggleOrthographic, vec![Key(KeyCode::F2)]),
+            (Action::Screenshot, vec![Key(KeyCode::F12)]),
+            (Action::RecordSequence, vec![Key(KeyCode::F10)]),
+            (Action::Rebind, vec![Key(KeyCode::F3)]),
+        ]))
+    }
+}
+
+impl Bindings {
+    pub fn actions(&self, binding: Binding) -> impl Iterator<Item = Action> + '_ {
+        self.0
+            .iter()
+            .filter(move |(_, bindings)| bindings.contains(&binding))
+            .map(|(action, _)| *action)
+    }
+
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.0.get(&action).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Replaces the bindings of `action` on the same kind of device, so
+    /// rebinding a key keeps the gamepad binding
+    pub fn rebind(&mut self, action: Action, binding: Binding) {
+        let bindings = self.0.entry(action).or_default();
+        bindings.retain(|b| std::mem::discriminant(b) != std::mem::discriminant(&binding));
+        bindings.push(binding);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+    Pressed(Action),
+    Released(Action),
+    /// A rebind started with [Action::Rebind] or [Input::start_rebind]
+    /// finished
+    Rebound(Action, Binding),
+}
+
+/// What the next pressed input is for during a rebind
+#[derive(Clone, Copy, Debug)]
+enum Rebinding {
+    /// Picks the action through its current binding
+    Action,
+    Binding {
+        action: Action,
+        /// Input the action was picked with, pressing it again picks the
+        /// next action it triggers
+        picked_with: Option<Binding>,
+    },
+}
+
+/// Turns device input into actions through the [Bindings]
+#[derive(Default)]
+pub struct Input {
+    pub bindings: Bindings,
+    held: HashSet<Binding>,
+    /// Analog values from gamepad axes, from 0 to 1
+    analog: HashMap<Action, f32>,
+    rebinding: Option<Rebinding>,
+}
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Self {
+        Self {
+            bindings,
+            ..Default::default()
+        }
+    }
+
+    /// Binds the next pressed input to `action`
+    pub fn start_rebind(&mut self, action: Action) {
+        log::info!("Press an input for {action:?}");
+        self.rebinding = Some(Rebinding::Binding {
+            action,
+            picked_with: None,
+        });
+    }
+
+    pub fn is_rebinding(&self) -> bool {
+        self.rebinding.is_some()
+    }
+
+    /// Feeds a press or release, returns the actions it started or ended
+    pub fn handle(&mut self, binding: Binding, pressed: bool) -> Vec<InputEvent> {
+        if pressed && let Some(rebinding) = self.rebinding.take() {
+            match rebinding {
+                Rebinding::Binding {
+                    action,
+                    picked_with,
+                } if picked_with != Some(binding) => {
+                    self.bindings.rebind(action, binding);
+                    log::info!("Bound {action:?} to {binding:?}");
+                    return vec![InputEvent::Rebound(action, binding)];
+                }
+                Rebinding::Binding { action, .. } => self.pick_rebind(binding, Some(action)),
+                Rebinding::Action => self.pick_rebind(binding, None),
+            }
+            return Vec::new();
+        }
+
+        let changed = if pressed {
+            self.held.insert(binding)
+        } else {
+            self.held.remove(&binding)
+        };
+        if !changed {
+            return Vec::new();
+        }
+
+        let events: Vec<_> = self
+            .bindings
+            .actions(binding)
+            // Held through another input
+            .filter(|action| pressed || !self.is_held(*action))
+            .map(|action| {
+                if pressed {
+                    InputEvent::Pressed(action)
+                } else {
+                    InputEvent::Released(action)
+                }
+            })
+            .collect();
+
+        if events.contains(&InputEvent::Pressed(Action::Rebind)) {
+            log::info!("Press an input of the action to rebind");
+            self.rebinding = Some(Rebinding::Action);
+        }
+        events
+    }
+
+    /// Picks the action after `current` among the ones `binding` triggers,
+    /// the first one without `current`
+    fn pick_rebind(&mut self, binding: Binding, current: Option<Action>) {
+        let actions: Vec<_> = self
+            .bindings
+            .actions(binding)
+            .filter(|action| *action != Action::Rebind)
+            .collect();
+        let next = current
+            .and_then(|current| actions.iter().position(|a| *a == current))
+            .map_or(0, |index| (index + 1) % actions.len().max(1));
+
+        let Some(&action) = actions.get(next) else {
+            log::info!("{binding:?} isn't bound, rebind cancelled");
+            return;
+        };
+        if actions.len() > 1 {
+            log::info!("Rebinding {action:?}, press {binding:?} again for the next action");
+        } else {
+            log::info!("Press an input for {action:?}");
+        }
+        self.rebinding = Some(Rebinding::Binding {
+            action,
+            picked_with: Some(binding),
+        });
+    }
+
+    /// Sets the analog value of an action, for sticks and triggers
+    pub fn set_analog(&mut self, action: Action, value: f32) {
+        self.analog.insert(action, value.clamp(0.0, 1.0));
+    }
+
+    pub fn is_held(&self, action: Action) -> bool {
+        self.bindings
+            .get(action)
+            .iter()
+            .any(|binding| self.held.contains(binding))
+    }
+
+    /// 1 while held, the analog value otherwise. Nothing moves while a
+    /// rebind waits for its input.
+    pub fn value(&self, action: Action) -> f32 {
+        if self.is_rebinding() {
+            0.0
+        } else if self.is_held(action) {
+            1.0
+        } else {
+            self.analog.get(&action).copied().unwrap_or(0.0)
+        }
+    }
+
+    /// `positive` minus `negative`, from -1 to 1
+    pub fn axis(&self, positive: Action, negative: Action) -> f32 {
+        self.value(positive) - self.value(negative)
+    }
+
+    /// Forgets held inputs, when the window loses focus releases never arrive
+    pub fn clear(&mut self) {
+        self.held.clear();
+        self.analog.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binding_triggers_every_bound_action() {
+        let mut input = Input::default();
+        let events = input.handle(Binding::Mouse(MouseButton::Left), true);
+
+        assert_eq!(
+            events,
+            [
+                InputEvent::Pressed(Action::Throw),
+                InputEvent::Pressed(Action::Aim)
+            ]
+        );
+        assert!(input.is_held(Action::Aim));
+        // Repeats don't press again
+        assert!(
+            input
+                .handle(Binding::Mouse(MouseButton::Left), true)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn action_held_by_two_inputs() {
+        let mut input = Input::default();
+        let pad = Binding::Gamepad(GamepadButton::RightTrigger);
+        let mouse = Binding::Mouse(MouseButton::Left);
+
+        input.handle(pad, true);
+        input.handle(mouse, true);
+        assert_eq!(input.handle(pad, false), []);
+        assert!(input.is_held(Action::Throw));
+    }
+
+    #[test]
+    fn rebind_keeps_other_devices() {
+        let mut input = Input::default();
+        input.start_rebind(Action::MoveForward);
+
+        let key = Binding::Key(KeyCode::ArrowUp);
+        assert_eq!(
+            input.handle(key, true),
+            [InputEvent::Rebound(Action::MoveForward, key)]
+        );
+        assert_eq!(
+            input.bindings.get(Action::MoveForward),
+            [Binding::Gamepad(GamepadButton::DPadUp), key]
+        );
+
+        input.handle(key, true);
+        assert_eq!(input.axis(Action::MoveForward, Action::MoveBackward), 1.0);
+    }
+
+    #[test]
+    fn rebind_key_picks_the_action_by_its_input() {
+        let mut input = Input::default();
+        let rebind = Binding::Key(KeyCode::F3);
+        input.handle(rebind, true);
+        input.handle(rebind, false);
+
+        // W picks MoveForward without moving, the next key replaces it
+        assert_eq!(input.handle(Binding::Key(KeyCode::KeyW), true), []);
+        assert_eq!(input.handle(Binding::Key(KeyCode::KeyW), false), []);
+        let key = Binding::Key(KeyCode::ArrowUp);
+        assert_eq!(
+            input.handle(key, true),
+            [InputEvent::Rebound(Action::MoveForward, key)]
+        );
+        assert_eq!(
+            input.bindings.get(Action::MoveForward),
+            [Binding::Gamepad(GamepadButton::DPadUp), key]
+        );
+    }
+
+    #[test]
+    fn rebind_key_cycles_through_shared_inputs() {
+        let mut input = Input::default();
+        let mouse = Binding::Mouse(MouseButton::Left);
+        input.handle(Binding::Key(KeyCode::F3), true);
+
+        // Left click throws and aims, the second click picks Aim
+        assert_eq!(input.handle(mouse, true), []);
+        input.handle(mouse, false);
+        assert_eq!(input.handle(mouse, true), []);
+        input.handle(mouse, false);
+
+        let right = Binding::Mouse(MouseButton::Right);
+        assert_eq!(
+            input.handle(right, true),
+            [InputEvent::Rebound(Action::Aim, right)]
+        );
+        assert_eq!(input.bindings.get(Action::Aim), [right]);
+        assert_eq!(input.bindings.get(Action::Throw)[0], mouse);
+    }
+
+    #[test]
+    fn bindings_round_trip() {
+        let bindings = Bindings::default();
+        let source = ron::to_string(&bindings).unwrap();
+        assert_eq!(ron::from_str::<Bindings>(&source).unwrap(), bindings);
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    collections::HashMap,
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use anyhow::Result;
+use glam::{Mat3, Mat4, Vec2, Vec3};
+use gltf::{Gltf, Node, Primitive};
+use serde::Deserialize;
+
+use crate::{
+    physics::PhysicsSettings,
+    player::PlayerSettings,
+    renderer::{
+        Renderer,
+        pipeline::{
+            InstanceRaw, background::Background, bloom::BloomSettings, color::ColoredVertex,
+            post::PostSettings, texture::TexturedVertex,
+        },
+    },
+};
+
+pub const DEFAULT_LEVEL: &str = "assets/levels/default.ron";
+
+pub type TexturedMeshes = HashMap<String, (Vec<TexturedVertex>, Vec<u16>, Vec<u8>)>;
+pub type ColoredMeshes = HashMap<String, (Vec<ColoredVertex>, Vec<u16>, [f32; 4])>;
+
+/// Level manifest, stored as RON in `assets/levels`
+#[derive(Debug, Deserialize)]
+pub struct Level {
+    /// glTF binary with the level geometry
+    pub map: String,
+    pub music: Option<String>,
+    #[serde(default)]
+    pub physics: PhysicsSettings,
+    #[serde(default)]
+    pub player: PlayerSettings,
+    /// Names of the meshes that shatter when the player runs into them
+    #[serde(default)]
+    pub glass: Vec<String>,
+    /// Positions the player passes on the way through the level
+    #[serde(default)]
+    pub checkpoints: Vec<Vec3>,
+    /// Particle effects file, [DEFAULT_PARTICLES] without one
+    ///
+    /// [DEFAULT_PARTICLES]: crate::particle_effects::DEFAULT_PARTICLES
+    pub particles: Option<String>,
+    /// Fog gradient, procedural sky or skybox
+    #[serde(default)]
+    pub background: Background,
+    #[serde(default)]
+    pub bloom: Keyframes<BloomSettings>,
+    /// Tone mapping and the other post-processing parameters
+    #[serde(default)]
+    pub post: PostSettings,
+}
+
+impl Level {
+    pub fn load(path: &str) -> Result<Self> {
+        log::info!("Loading level manifest {path}");
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+/// Values that can be blended, so level settings can be animated
+pub trait Lerp {
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+/// `(time, value)` pairs in seconds since the level started, sorted by time.
+/// Values are interpolated between keys and held before the first and after
+/// the last one.
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub struct Keyframes<T>(pub Vec<(f32, T)>);
+
+impl<T> Default for Keyframes<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<T: Lerp + Clone> Keyframes<T> {
+    /// `None` when there are no keys
+    pub fn sample(&self, time: f32) -> Option<T> {
+        let next = self.0.iter().position(|(key_time, _)| *key_time > time);
+
+        match next {
+            Some(0) => self.0.first().map(|(_, value)| value.clone()),
+            Some(next) => {
+                let (start, from) = &self.0[next - 1];
+                let (end, to) = &self.0[next];
+                Some(from.lerp(to, (time - start) / (end - start)))
+            }
+            None => self.0.last().map(|(_, value)| value.clone()),
+        }
+    }
+}
+
+/// Geometry read from the level glTF. This is plain CPU data, so it can be
+/// used for colliders without a GPU and uploaded to the renderer separately.
+#[derive(Default)]
+pub struct LevelMeshes {
+    pub instances: HashMap<String, Vec<InstanceRaw>>,
+    pub textured: TexturedMeshes,
+    pub colored: ColoredMeshes,
+    pub colliders: HashMap<String, (Vec<Vec3>, Vec<u16>)>,
+}
+
+impl LevelMeshes {
+    pub fn load(path: &str) -> Result<Self> {
+        log::info!("Loading level meshes from {path}");
+
+        let gltf = Gltf::from_slice(&fs::read(path)?)?;
+        let mut meshes = Self::default();
+
+        if let Some(blob) = &gltf.blob {
+            log::info!("Data collection");
+            for node in gltf.nodes() {
+                meshes.add_node(node, blob);
+            }
+        }
+
+        Ok(meshes)
+    }
+
+    pub fn add_node(&mut self, node: Node, blob: &[u8]) {
+        let Some(mesh) = node.mesh() else { return };
+        let Some(name) = mesh.name() else { return };
+
+        let model_matrix = Mat3::from_mat4(Mat4::from_cols_array_2d(&node.transform().matrix()));
+        let normal_matrix = model_matrix.inverse().transpose();
+
+        if let Some((base_name, _)) = name.split_once('.') {
+            self.instances
+                .entry(base_name.to_string())
+                .or_default()
+                .push(InstanceRaw {
+                    model: node.transform().matrix(),
+                    normal: normal_matrix.to_cols_array_2d(),
+                });
+            return;
+        }
+
+        for primitive in mesh.primitives() {
+            self.add_primitive(primitive, name, blob);
+        }
+    }
+
+    pub fn add_primitive(&mut self, primitive: Primitive, name: &str, blob: &[u8]) {
+        let reader = primitive.reader(|buffer| {
+            if buffer.index() == 0 {
+                Some(blob)
+            } else {
+                None
+            }
+        });
+
+        let indices: Vec<u16> = match reader
+            .read_indices()
+            .map(|i| i.into_u32().map(|v| v as u16))
+        {
+            Some(indices) => indices.collect(),
+            None => return,
+        };
+
+        let positions: Vec<Vec3> = reader.read_positions().unwrap().map(Vec3::from).collect();
+        if positions.is_empty() {
+            log::warn!("Mesh '{name}' has no positions, skipping");
+            return;
+        }
+
+        let normals = match reader.read_normals() {
+            Some(n) => n.map(Vec3::from).collect(),
+            None => {
+                log::warn!("Normals not found for '{name}', generating...");
+                Renderer::compute_normals(&positions, &indices)
+            }
+        };
+
+        if let Some(tex_coords) = reader
+            .read_tex_coords(0)
+            .map(|t| t.into_f32().map(Vec2::from).collect::<Vec<_>>())
+        {
+            log::info!("Finded texture coords of {name}, trying load texture info");
+
+            if let Some(texture_info) = primitive
+                .material()
+                .pbr_metallic_roughness()
+                .base_color_texture()
+            {
+                log::info!("Texture info loaded, trying get texture");
+                if let gltf::image::Source::View { view, .. } =
+                    texture_info.texture().source().source()
+                {
+                    log::info!("Try load texture mesh");
+
+                    let image_data = &blob[view.offset()..view.offset() + view.length()];
+
+                    let vertices = positions
+                        .iter()
+                        .zip(tex_coords.iter())
+                        .zip(normals.iter())
+                        .map(|((pos, uv), normal)| TexturedVertex {
+                            position: pos.to_array(),
+                            tex_coords: [uv.x, uv.y],
+                            normal: normal.to_array(),
+                        })
+                        .collect();
+
+                    self.colliders
+                        .insert(name.to_string(), (positions, indices.clone()));
+                    self.textured
+                        .insert(name.to_string(), (vertices, indices, image_data.to_vec()));
+                    return;
+                }
+            }
+        }
+
+        let colors = reader
+            .read_colors(0)
+            .map(|c| c.into_rgba_f32().map(|v| [v[0], v[1], v[2]]).collect())
+            .unwrap_or_else(|| {
+                let base = primitive
+                    .material()
+                    .pbr_metallic_roughness()
+                    .base_color_factor();
+                vec![[base[0], base[1], base[2]]; positions.len()]
+            });
+
+        let vertices = positions
+            .iter()
+            .zip(colors.iter())
+            .zip(normals.iter())
+            .map(|((pos, color), normal)| ColoredVertex {
+                position: pos.to_array(),
+                color: *color,
+                normal: normal.to_array(),
+            })
+            .collect();
+
+        self.colliders
+            .insert(name.to_string(), (positions, indices.clone()));
+
+        self.colored.insert(
+            name.to_string(),
+            (
+                vertices,
+                indices,
+                primitive
+                    .material()
+                    .pbr_metallic_roughness()
+                    .base_color_factor(),
+            ),
+        );
+    }
+}
+
+pub fn hash_string_to_u64(s: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    s.hash(&mut hasher);
+    hasher.finish()
+}
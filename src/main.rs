@@ -1,17 +1,55 @@
 use anyhow::Result;
 use game::Game;
+use headless::Headless;
 use log::Level;
+use replay::Recording;
 use winit::event_loop::EventLoop;
 
+pub mod ball_pool;
 pub mod camera_controller;
+pub mod camera_effects;
 pub mod game;
+pub mod gamepad;
+pub mod headless;
+pub mod input;
+pub mod level;
+pub mod particle_effects;
 pub mod physics;
+pub mod player;
 pub mod renderer;
+pub mod replay;
 pub mod scene;
+pub mod settings;
+pub mod touch;
+pub mod world;
 
 fn main() -> Result<()> {
     simple_logger::init_with_level(Level::Info)?;
-    EventLoop::new()?.run_app(&mut Game::default())?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--headless") {
+        Headless::from_args(&args)?.run()?;
+        return Ok(());
+    }
+
+    if let Some(path) = arg_value(&args, "--replay") {
+        Recording::load(path)?.replay()?;
+        return Ok(());
+    }
+
+    let mut game = Game::default();
+    if let Some(path) = arg_value(&args, "--record") {
+        game.record_to(path);
+    }
+
+    EventLoop::new()?.run_app(&mut game)?;
 
     Ok(())
 }
+
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
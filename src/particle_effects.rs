@@ -0,0 +1,60 @@
+use std::{collections::HashMap, fs};
+
+use anyhow::Result;
+use glam::Vec3;
+use serde::Deserialize;
+
+use crate::{
+    camera_effects::GameEvent,
+    renderer::pipeline::particle::{EmitterSettings, ParticlePipeline},
+};
+
+pub const DEFAULT_PARTICLES: &str = "assets/particles/default.ron";
+
+/// Impact speed that gives a full burst, slower impacts emit fewer particles
+const FULL_IMPACT_SPEED: f32 = 12.0;
+
+/// Emitters for every kind of event, stored as RON. The keys are
+/// `ball_impact`, `glass_break` and `checkpoint`, each with a list of
+/// emitters that all fire. Missing keys emit nothing.
+#[derive(Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct ParticleEffects(pub HashMap<String, Vec<EmitterSettings>>);
+
+impl ParticleEffects {
+    pub fn load(path: &str) -> Result<Self> {
+        log::info!("Loading particle effects {path}");
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Emits the particles of an event, if it has any
+    pub fn handle(&self, event: GameEvent, particles: &mut ParticlePipeline, queue: &wgpu::Queue) {
+        let (name, position, direction, strength) = match event {
+            GameEvent::BallImpact {
+                position,
+                normal,
+                speed,
+            } => ("ball_impact", position, normal, speed / FULL_IMPACT_SPEED),
+            GameEvent::GlassBroken { position } => ("glass_break", position, Vec3::Y, 1.0),
+            GameEvent::Checkpoint { position } => ("checkpoint", position, Vec3::Y, 1.0),
+            GameEvent::PlayerHit { .. } | GameEvent::BallThrown | GameEvent::SpeedUp(_) => return,
+        };
+
+        for emitter in self.0.get(name).into_iter().flatten() {
+            particles.emit(queue, emitter, position, direction, strength);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_effects_load() {
+        let effects = ParticleEffects::load(DEFAULT_PARTICLES).unwrap();
+        for name in ["ball_impact", "glass_break", "checkpoint"] {
+            assert!(!effects.0[name].is_empty(), "{name} has no emitters");
+        }
+    }
+}
@@ -1,17 +1,264 @@
-use glam::Vec3;
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::Result;
+use glam::{Quat, Vec3};
 use rapier3d::{
-    math::Vector,
+    control::KinematicCharacterController,
+    math::{Isometry, Point, Vector},
     na::Vector3,
+    parry::{query::ShapeCastOptions, shape::Shape},
     prelude::{
-        BroadPhaseMultiSap, CCDSolver, ColliderBuilder, ColliderHandle, ColliderSet,
-        ImpulseJointSet, IntegrationParameters, IslandManager, MultibodyJointSet, NarrowPhase,
-        PhysicsPipeline, QueryPipeline, RigidBodyBuilder, RigidBodyHandle, RigidBodySet,
+        ActiveEvents, BroadPhaseMultiSap, CCDSolver, Collider, ColliderBuilder, ColliderHandle,
+        ColliderSet, CollisionEvent, ContactPair, EventHandler, Group, ImpulseJointSet,
+        IntegrationParameters, InteractionGroups, IslandManager, MultibodyJointSet, NarrowPhase,
+        PhysicsPipeline, QueryFilter, QueryPipeline, Ray, Real, RigidBodyBuilder, RigidBodyHandle,
+        RigidBodySet, RigidBodyType,
     },
 };
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum Solver {
+    TgsSoft,
+    TgsSoftWithoutWarmstart,
+    PgsLegacy,
+}
+
+impl Solver {
+    pub fn integration_parameters(self) -> IntegrationParameters {
+        match self {
+            Self::TgsSoft => IntegrationParameters::tgs_soft(),
+            Self::TgsSoftWithoutWarmstart => IntegrationParameters::tgs_soft_without_warmstart(),
+            Self::PgsLegacy => IntegrationParameters::pgs_legacy(),
+        }
+    }
+}
+
+/// Which body types get continuous collision detection. Balls are dynamic,
+/// so `dynamic` keeps them from tunneling through thin glass.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct CcdSettings {
+    pub dynamic: bool,
+    pub kinematic: bool,
+}
+
+impl Default for CcdSettings {
+    fn default() -> Self {
+        Self {
+            dynamic: true,
+            kinematic: false,
+        }
+    }
+}
+
+impl CcdSettings {
+    pub fn enabled_for(&self, body_type: RigidBodyType) -> bool {
+        match body_type {
+            RigidBodyType::Dynamic => self.dynamic,
+            RigidBodyType::KinematicPositionBased | RigidBodyType::KinematicVelocityBased => {
+                self.kinematic
+            }
+            RigidBodyType::Fixed => false,
+        }
+    }
+}
+
+/// Per-level simulation parameters, read from the `physics` section of the level manifest
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct PhysicsSettings {
+    pub gravity: Vec3,
+    pub solver: Solver,
+    pub substeps: u8,
+    pub ccd: CcdSettings,
+    pub friction: f32,
+    pub restitution: f32,
+}
+
+impl Default for PhysicsSettings {
+    fn default() -> Self {
+        Self {
+            gravity: Vec3::new(0.0, -9.81, 0.0),
+            solver: Solver::TgsSoft,
+            substeps: 1,
+            ccd: CcdSettings::default(),
+            friction: 0.5,
+            restitution: 0.0,
+        }
+    }
+}
+
+/// Fixed-rate simulation clock. Frame time is accumulated and consumed in
+/// whole ticks, the remainder is used to interpolate rendering.
+pub struct Timestep {
+    pub tick_rate: f32,
+    pub time_scale: f32,
+    /// Upper bound of ticks per frame, so a long stall doesn't spiral
+    pub max_ticks: u32,
+    accumulator: f32,
+}
+
+impl Default for Timestep {
+    fn default() -> Self {
+        Self::new(60.0)
+    }
+}
+
+impl Timestep {
+    pub fn new(tick_rate: f32) -> Self {
+        Self {
+            tick_rate,
+            time_scale: 1.0,
+            max_ticks: 8,
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn dt(&self) -> f32 {
+        1.0 / self.tick_rate
+    }
+
+    /// Adds frame time and returns how many ticks should be simulated
+    pub fn advance(&mut self, frame_secs: f32) -> u32 {
+        let dt = self.dt();
+        self.accumulator += frame_secs * self.time_scale;
+
+        let ticks = (self.accumulator / dt) as u32;
+        if ticks > self.max_ticks {
+            self.accumulator = 0.0;
+            return self.max_ticks;
+        }
+
+        self.accumulator -= ticks as f32 * dt;
+        ticks
+    }
+
+    /// How far we are between the previous and the current tick
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.dt()).clamp(0.0, 1.0)
+    }
+}
+
+/// Collision groups of the scene, queries pick which of them they hit
+pub mod groups {
+    use rapier3d::prelude::Group;
+
+    /// Level geometry, walls and glass
+    pub const LEVEL: Group = Group::GROUP_1;
+    pub const BALLS: Group = Group::GROUP_2;
+    pub const PLAYER: Group = Group::GROUP_3;
+}
+
+/// First thing a ray or shape cast ran into
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QueryHit {
+    /// Scene object id, see [Physics::object_id]
+    pub id: u128,
+    pub collider: ColliderHandle,
+    /// Distance travelled along the cast direction
+    pub distance: f32,
+    pub point: Vec3,
+    pub normal: Vec3,
+}
+
+/// Closest point on the surface of a collider
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProjectedPoint {
+    pub id: u128,
+    pub collider: ColliderHandle,
+    pub point: Vec3,
+    /// Whether the projected point was inside the collider
+    pub inside: bool,
+}
+
+/// A ball ran into something during [Physics::step]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Impact {
+    /// Scene object id of the ball
+    pub id: u128,
+    pub position: Vec3,
+    /// Points away from what the ball hit
+    pub normal: Vec3,
+    /// Speed the ball hit with, along the normal
+    pub speed: f32,
+}
+
+/// Slower contacts, like balls rolling to a stop, aren't impacts
+const MIN_IMPACT_SPEED: f32 = 1.0;
+
+/// Turns contacts that start during a step into [Impact]s
+#[derive(Default)]
+struct ImpactCollector(Mutex<Vec<Impact>>);
+
+impl ImpactCollector {
+    fn impact(
+        ball: ColliderHandle,
+        bodies: &RigidBodySet,
+        colliders: &ColliderSet,
+        pair: &ContactPair,
+    ) -> Option<Impact> {
+        let (manifold, contact) = pair.find_deepest_contact()?;
+        let position = colliders[pair.collider1].position() * contact.local_p1;
+
+        // The manifold normal points from the first collider to the second
+        let normal = Vector::from(manifold.data.normal);
+        let normal = if ball == pair.collider1 {
+            -normal
+        } else {
+            normal
+        };
+
+        let ball = &colliders[ball];
+        let velocity = bodies[ball.parent()?].linvel();
+        let speed = -velocity.dot(&normal);
+        (speed >= MIN_IMPACT_SPEED).then(|| Impact {
+            id: ball.user_data,
+            position: Vec3::new(position.x, position.y, position.z),
+            normal: Vec3::new(normal.x, normal.y, normal.z),
+            speed,
+        })
+    }
+}
+
+impl EventHandler for ImpactCollector {
+    fn handle_collision_event(
+        &self,
+        bodies: &RigidBodySet,
+        colliders: &ColliderSet,
+        event: CollisionEvent,
+        contact_pair: Option<&ContactPair>,
+    ) {
+        let (CollisionEvent::Started(..), Some(pair)) = (event, contact_pair) else {
+            return;
+        };
+
+        for ball in [pair.collider1, pair.collider2] {
+            if colliders[ball]
+                .collision_groups()
+                .memberships
+                .contains(groups::BALLS)
+                && let Some(impact) = Self::impact(ball, bodies, colliders, pair)
+            {
+                self.0.lock().unwrap().push(impact);
+            }
+        }
+    }
+
+    fn handle_contact_force_event(
+        &self,
+        _dt: Real,
+        _bodies: &RigidBodySet,
+        _colliders: &ColliderSet,
+        _contact_pair: &ContactPair,
+        _total_force_magnitude: Real,
+    ) {
+    }
+}
 
 pub struct Physics {
     pub pipeline: PhysicsPipeline,
-    pub gravity: Vec3,
+    pub settings: PhysicsSettings,
     pub integration_parameters: IntegrationParameters,
     pub islands: IslandManager,
     pub broad_phase: BroadPhaseMultiSap,
@@ -21,7 +268,12 @@ pub struct Physics {
     pub impulse_joints: ImpulseJointSet,
     pub multibody_joints: MultibodyJointSet,
     pub ccd_solver: CCDSolver,
+    /// Acceleration structure for scene queries, updated by every step
     pub query_pipeline: Option<QueryPipeline>,
+    /// Dynamic body positions before the last step, used for interpolation
+    pub previous_positions: HashMap<RigidBodyHandle, Isometry<f32>>,
+    /// Balls running into things during the last step
+    pub impacts: Vec<Impact>,
 }
 
 impl Default for Physics {
@@ -32,10 +284,14 @@ impl Default for Physics {
 
 impl Physics {
     pub fn new() -> Self {
+        Self::with_settings(PhysicsSettings::default())
+    }
+
+    pub fn with_settings(settings: PhysicsSettings) -> Self {
         Self {
             pipeline: PhysicsPipeline::new(),
-            gravity: Vec3::default(),
-            integration_parameters: IntegrationParameters::tgs_soft(),
+            settings,
+            integration_parameters: settings.solver.integration_parameters(),
             islands: IslandManager::new(),
             broad_phase: BroadPhaseMultiSap::new(),
             narrow_phase: NarrowPhase::new(),
@@ -44,19 +300,81 @@ impl Physics {
             impulse_joints: ImpulseJointSet::new(),
             multibody_joints: MultibodyJointSet::new(),
             ccd_solver: CCDSolver::new(),
-            query_pipeline: None,
+            query_pipeline: Some(QueryPipeline::new()),
+            previous_positions: HashMap::new(),
+            impacts: Vec::new(),
+        }
+    }
+
+    /// Applies a whole new set of settings, e.g. when loading another level
+    pub fn apply_settings(&mut self, settings: PhysicsSettings) {
+        self.set_gravity(settings.gravity);
+        self.set_solver(settings.solver);
+        self.set_substeps(settings.substeps);
+        self.set_ccd(settings.ccd);
+        self.set_contact_material(settings.friction, settings.restitution);
+    }
+
+    pub fn set_gravity(&mut self, gravity: Vec3) {
+        self.settings.gravity = gravity;
+
+        // Sleeping bodies would otherwise ignore the change
+        for (_, body) in self.bodies.iter_mut() {
+            if body.is_dynamic() {
+                body.wake_up(true);
+            }
+        }
+    }
+
+    pub fn set_solver(&mut self, solver: Solver) {
+        self.settings.solver = solver;
+        self.integration_parameters = solver.integration_parameters();
+    }
+
+    pub fn set_substeps(&mut self, substeps: u8) {
+        self.settings.substeps = substeps.max(1);
+    }
+
+    /// Updates CCD on existing bodies and on the ones created later
+    pub fn set_ccd(&mut self, ccd: CcdSettings) {
+        self.settings.ccd = ccd;
+
+        for (_, body) in self.bodies.iter_mut() {
+            body.enable_ccd(ccd.enabled_for(body.body_type()));
         }
     }
 
-    pub fn step(&mut self, delta_secs: f32, max_dt: f32, time_scale: f32, substeps: u8) {
-        self.integration_parameters.dt = (delta_secs * time_scale).min(max_dt);
+    /// Sets friction and restitution on all existing colliders and the ones created later
+    pub fn set_contact_material(&mut self, friction: f32, restitution: f32) {
+        self.settings.friction = friction;
+        self.settings.restitution = restitution;
 
+        for (_, collider) in self.colliders.iter_mut() {
+            collider.set_friction(friction);
+            collider.set_restitution(restitution);
+        }
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        self.previous_positions.clear();
+        self.previous_positions.extend(
+            self.bodies
+                .iter()
+                .filter(|(_, body)| body.is_dynamic() && body.is_enabled())
+                .map(|(handle, body)| (handle, *body.position())),
+        );
+
+        self.integration_parameters.dt = dt;
+
+        let substeps = self.settings.substeps.max(1);
         let mut substep_integration_parameters = self.integration_parameters;
         substep_integration_parameters.dt /= substeps as f32;
 
+        let gravity = self.settings.gravity;
+        let impacts = ImpactCollector::default();
         for _ in 0..substeps {
             self.pipeline.step(
-                &Vector3::new(self.gravity.x, self.gravity.y, self.gravity.z),
+                &Vector3::new(gravity.x, gravity.y, gravity.z),
                 &substep_integration_parameters,
                 &mut self.islands,
                 &mut self.broad_phase,
@@ -68,9 +386,212 @@ impl Physics {
                 &mut self.ccd_solver,
                 self.query_pipeline.as_mut(),
                 &(),
-                &(),
+                &impacts,
             );
         }
+        self.impacts = impacts.0.into_inner().unwrap();
+    }
+
+    /// Rebuilds the query pipeline, for colliders added or moved outside of [Self::step]
+    pub fn update_queries(&mut self) {
+        if let Some(queries) = &mut self.query_pipeline {
+            queries.update(&self.colliders);
+        }
+    }
+
+    /// Scene object id of a collider, `(mesh_id << 64) | instance_index`
+    pub fn object_id(&self, collider: ColliderHandle) -> u128 {
+        self.colliders
+            .get(collider)
+            .map_or(0, |collider| collider.user_data)
+    }
+
+    /// Casts a ray against the colliders in `groups`
+    pub fn cast_ray(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+        groups: Group,
+    ) -> Option<QueryHit> {
+        let queries = self.query_pipeline.as_ref()?;
+        let direction = direction.try_normalize()?;
+        let ray = Ray::new(point(origin), vector(direction));
+
+        let predicate = |_, collider: &Collider| self.is_active(collider);
+        let (collider, hit) = queries.cast_ray_and_get_normal(
+            &self.bodies,
+            &self.colliders,
+            &ray,
+            max_distance,
+            true,
+            filter(groups, &predicate),
+        )?;
+
+        Some(QueryHit {
+            id: self.object_id(collider),
+            collider,
+            distance: hit.time_of_impact,
+            point: origin + direction * hit.time_of_impact,
+            normal: Vec3::new(hit.normal.x, hit.normal.y, hit.normal.z),
+        })
+    }
+
+    /// Sweeps `shape` from `position` along `direction` against the colliders in `groups`
+    pub fn cast_shape(
+        &self,
+        shape: &dyn Shape,
+        position: Vec3,
+        rotation: Quat,
+        direction: Vec3,
+        max_distance: f32,
+        groups: Group,
+    ) -> Option<QueryHit> {
+        let queries = self.query_pipeline.as_ref()?;
+        let direction = direction.try_normalize()?;
+
+        let predicate = |_, collider: &Collider| self.is_active(collider);
+        let (collider, hit) = queries.cast_shape(
+            &self.bodies,
+            &self.colliders,
+            &isometry(position, rotation),
+            &vector(direction),
+            shape,
+            ShapeCastOptions::with_max_time_of_impact(max_distance),
+            filter(groups, &predicate),
+        )?;
+
+        // The witness is on the collider, in the frame of the scene
+        Some(QueryHit {
+            id: self.object_id(collider),
+            collider,
+            distance: hit.time_of_impact,
+            point: Vec3::new(hit.witness1.x, hit.witness1.y, hit.witness1.z),
+            normal: Vec3::new(hit.normal1.x, hit.normal1.y, hit.normal1.z),
+        })
+    }
+
+    /// Moves `shape` from `position` by `translation` with `controller`,
+    /// sliding along the colliders in `groups`. Returns the translation it
+    /// managed and the ids of the colliders it ran into, in order.
+    pub fn move_character(
+        &self,
+        controller: &KinematicCharacterController,
+        shape: &dyn Shape,
+        position: Vec3,
+        translation: Vec3,
+        groups: Group,
+        dt: f32,
+    ) -> (Vec3, Vec<u128>) {
+        let Some(queries) = &self.query_pipeline else {
+            return (translation, Vec::new());
+        };
+
+        let predicate = |_, collider: &Collider| self.is_active(collider);
+        let mut obstacles = Vec::new();
+        let movement = controller.move_shape(
+            dt,
+            &self.bodies,
+            &self.colliders,
+            queries,
+            shape,
+            &isometry(position, Quat::IDENTITY),
+            vector(translation),
+            filter(groups, &predicate),
+            |collision| obstacles.push(self.object_id(collision.handle)),
+        );
+
+        let moved = movement.translation;
+        (Vec3::new(moved.x, moved.y, moved.z), obstacles)
+    }
+
+    /// Projects `position` on the closest collider in `groups`
+    pub fn project_point(&self, position: Vec3, groups: Group) -> Option<ProjectedPoint> {
+        let queries = self.query_pipeline.as_ref()?;
+
+        let predicate = |_, collider: &Collider| self.is_active(collider);
+        let (collider, projection) = queries.project_point(
+            &self.bodies,
+            &self.colliders,
+            &point(position),
+            false,
+            filter(groups, &predicate),
+        )?;
+
+        let projected = projection.point;
+        Some(ProjectedPoint {
+            id: self.object_id(collider),
+            collider,
+            point: Vec3::new(projected.x, projected.y, projected.z),
+            inside: projection.is_inside,
+        })
+    }
+
+    /// Ids of the colliders in `groups` that overlap `shape`
+    pub fn intersect_shape(
+        &self,
+        shape: &dyn Shape,
+        position: Vec3,
+        rotation: Quat,
+        groups: Group,
+    ) -> Vec<u128> {
+        let mut ids = Vec::new();
+        let Some(queries) = &self.query_pipeline else {
+            return ids;
+        };
+
+        let predicate = |_, collider: &Collider| self.is_active(collider);
+        queries.intersections_with_shape(
+            &self.bodies,
+            &self.colliders,
+            &isometry(position, rotation),
+            shape,
+            filter(groups, &predicate),
+            |collider| {
+                ids.push(self.object_id(collider));
+                true
+            },
+        );
+        ids
+    }
+
+    /// Ids of the colliders in `groups` that contain `position`
+    pub fn intersect_point(&self, position: Vec3, groups: Group) -> Vec<u128> {
+        let mut ids = Vec::new();
+        let Some(queries) = &self.query_pipeline else {
+            return ids;
+        };
+
+        let predicate = |_, collider: &Collider| self.is_active(collider);
+        queries.intersections_with_point(
+            &self.bodies,
+            &self.colliders,
+            &point(position),
+            filter(groups, &predicate),
+            |collider| {
+                ids.push(self.object_id(collider));
+                true
+            },
+        );
+        ids
+    }
+
+    /// Parked pool balls stay in the query pipeline, skip them
+    fn is_active(&self, collider: &Collider) -> bool {
+        collider.is_enabled()
+            && collider
+                .parent()
+                .is_none_or(|body| self.bodies.get(body).is_some_and(|body| body.is_enabled()))
+    }
+
+    /// Blends the body position between the previous and the current step
+    pub fn interpolated_position(&self, handle: RigidBodyHandle, alpha: f32) -> Isometry<f32> {
+        let current = *self.bodies[handle].position();
+
+        match self.previous_positions.get(&handle) {
+            Some(previous) => previous.lerp_slerp(&current, alpha),
+            None => current,
+        }
     }
 
     pub fn create_ball(
@@ -85,15 +606,281 @@ impl Physics {
                 .translation(Vector::new(position.x, position.y, position.z))
                 .linvel(Vector::new(velocity.x, velocity.y, velocity.z))
                 .user_data(id)
+                .ccd_enabled(self.settings.ccd.enabled_for(RigidBodyType::Dynamic))
                 .build(),
         );
 
         let collider = self.colliders.insert_with_parent(
-            ColliderBuilder::ball(radius).density(1.0).build(),
+            ColliderBuilder::ball(radius)
+                .density(1.0)
+                .friction(self.settings.friction)
+                .restitution(self.settings.restitution)
+                .user_data(id)
+                .collision_groups(InteractionGroups::new(groups::BALLS, Group::ALL))
+                .active_events(ActiveEvents::COLLISION_EVENTS)
+                .build(),
             rigid_body,
             &mut self.bodies,
         );
 
         (rigid_body, collider)
     }
+
+    /// Adds a fixed triangle mesh, e.g. level geometry or a glass pane. The
+    /// id goes on the collider, the body has none since it's never moved.
+    pub fn create_trimesh(
+        &mut self,
+        id: u128,
+        translation: Vec3,
+        rotation: Quat,
+        vertices: &[Vec3],
+        triangles: Vec<[u32; 3]>,
+    ) -> Result<(RigidBodyHandle, ColliderHandle)> {
+        let points = vertices.iter().map(|v| Point::new(v.x, v.y, v.z)).collect();
+
+        let collider = ColliderBuilder::trimesh(points, triangles)?
+            .friction(self.settings.friction)
+            .restitution(self.settings.restitution)
+            .user_data(id)
+            .collision_groups(InteractionGroups::new(groups::LEVEL, Group::ALL))
+            .build();
+
+        let axis = rotation.to_scaled_axis();
+        let rigid_body = self.bodies.insert(
+            RigidBodyBuilder::fixed()
+                .translation(Vector::new(translation.x, translation.y, translation.z))
+                .rotation(Vector::new(axis.x, axis.y, axis.z))
+                .build(),
+        );
+
+        let collider = self
+            .colliders
+            .insert_with_parent(collider, rigid_body, &mut self.bodies);
+
+        Ok((rigid_body, collider))
+    }
+}
+
+fn point(v: Vec3) -> Point<f32> {
+    Point::new(v.x, v.y, v.z)
+}
+
+fn vector(v: Vec3) -> Vector<f32> {
+    Vector::new(v.x, v.y, v.z)
+}
+
+fn isometry(translation: Vec3, rotation: Quat) -> Isometry<f32> {
+    Isometry::new(vector(translation), vector(rotation.to_scaled_axis()))
+}
+
+/// Query filter hitting the colliders in `groups` that pass `predicate`
+fn filter<'a>(
+    groups: Group,
+    predicate: &'a impl Fn(ColliderHandle, &Collider) -> bool,
+) -> QueryFilter<'a> {
+    QueryFilter::new()
+        .groups(InteractionGroups::new(Group::ALL, groups))
+        .predicate(predicate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PANE_Z: f32 = -5.0;
+    const PANE_ID: u128 = 7;
+
+    /// Zero-thickness 4x4 pane facing +Z, like the glass in the levels
+    fn add_pane(physics: &mut Physics) {
+        let vertices = [
+            Vec3::new(-2.0, -2.0, 0.0),
+            Vec3::new(2.0, -2.0, 0.0),
+            Vec3::new(2.0, 2.0, 0.0),
+            Vec3::new(-2.0, 2.0, 0.0),
+        ];
+
+        physics
+            .create_trimesh(
+                PANE_ID,
+                Vec3::new(0.0, 0.0, PANE_Z),
+                Quat::IDENTITY,
+                &vertices,
+                vec![[0, 1, 2], [0, 2, 3]],
+            )
+            .unwrap();
+    }
+
+    /// Fires a ball at the pane and returns its z after one second
+    fn fire(ccd: CcdSettings, speed: f32, dt: f32) -> f32 {
+        let mut physics = Physics::with_settings(PhysicsSettings {
+            gravity: Vec3::ZERO,
+            ccd,
+            ..Default::default()
+        });
+        add_pane(&mut physics);
+
+        let (ball, _) = physics.create_ball(1, Vec3::ZERO, Vec3::new(0.0, 0.0, -speed), 0.1);
+
+        for _ in 0..(1.0 / dt) as u32 {
+            physics.step(dt);
+        }
+
+        physics.bodies[ball].translation().z
+    }
+
+    /// Fast enough to cross the pane in a single step at either timestep
+    const FAST_SPEEDS: [f32; 3] = [50.0, 150.0, 500.0];
+
+    #[test]
+    fn ccd_stops_balls_that_tunnel_without_it() {
+        let without = CcdSettings {
+            dynamic: false,
+            ..Default::default()
+        };
+        for dt in [1.0 / 32.0, 1.0 / 60.0] {
+            for speed in FAST_SPEEDS {
+                // The control, the same shot goes through the pane
+                let z = fire(without, speed, dt);
+                assert!(
+                    z < PANE_Z,
+                    "ball at {speed} m/s with dt {dt} stopped at z = {z} without CCD"
+                );
+
+                let z = fire(CcdSettings::default(), speed, dt);
+                assert!(
+                    z > PANE_Z,
+                    "ball at {speed} m/s with dt {dt} tunneled to z = {z}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn balls_report_impacts() {
+        let mut physics = Physics::with_settings(PhysicsSettings {
+            gravity: Vec3::ZERO,
+            ..Default::default()
+        });
+        add_pane(&mut physics);
+        physics.create_ball(1, Vec3::ZERO, Vec3::new(0.0, 0.0, -10.0), 0.1);
+
+        let mut impacts = Vec::new();
+        for _ in 0..60 {
+            physics.step(1.0 / 60.0);
+            impacts.extend(physics.impacts.iter().copied());
+        }
+
+        // It stays against the pane, so the contact only starts once
+        assert_eq!(impacts.len(), 1, "{impacts:?}");
+        let impact = impacts[0];
+        assert_eq!(impact.id, 1);
+        assert!((impact.position.z - PANE_Z).abs() < 0.01);
+        assert!(impact.normal.abs_diff_eq(Vec3::Z, 1e-3));
+        assert!((impact.speed - 10.0).abs() < 0.5, "{}", impact.speed);
+    }
+
+    fn pane_scene() -> Physics {
+        let mut physics = Physics::new();
+        add_pane(&mut physics);
+        physics.update_queries();
+        physics
+    }
+
+    #[test]
+    fn ray_hits_pane() {
+        let physics = pane_scene();
+        let hit = physics
+            .cast_ray(Vec3::ZERO, Vec3::NEG_Z, 100.0, groups::LEVEL)
+            .unwrap();
+
+        assert_eq!(hit.id, PANE_ID);
+        assert!((hit.distance + PANE_Z).abs() < 1e-4);
+        assert!((hit.point.z - PANE_Z).abs() < 1e-4);
+
+        // Filtered out, too short and pointing away
+        assert!(
+            physics
+                .cast_ray(Vec3::ZERO, Vec3::NEG_Z, 100.0, groups::BALLS)
+                .is_none()
+        );
+        assert!(
+            physics
+                .cast_ray(Vec3::ZERO, Vec3::NEG_Z, 1.0, groups::LEVEL)
+                .is_none()
+        );
+        assert!(
+            physics
+                .cast_ray(Vec3::ZERO, Vec3::Z, 100.0, groups::LEVEL)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn shape_cast_stops_at_the_surface() {
+        let physics = pane_scene();
+        let sphere = rapier3d::prelude::Ball::new(0.5);
+        let hit = physics
+            .cast_shape(
+                &sphere,
+                Vec3::ZERO,
+                Quat::IDENTITY,
+                Vec3::NEG_Z,
+                100.0,
+                groups::LEVEL,
+            )
+            .unwrap();
+
+        assert_eq!(hit.id, PANE_ID);
+        assert!((hit.distance - (-PANE_Z - 0.5)).abs() < 1e-3);
+        assert!((hit.point.z - PANE_Z).abs() < 1e-3);
+    }
+
+    #[test]
+    fn point_queries() {
+        let mut physics = pane_scene();
+        let projected = physics
+            .project_point(Vec3::new(1.0, 1.0, 0.0), groups::LEVEL)
+            .unwrap();
+        assert_eq!(projected.id, PANE_ID);
+        assert!((projected.point - Vec3::new(1.0, 1.0, PANE_Z)).length() < 1e-4);
+
+        let (ball, _) = physics.create_ball(3, Vec3::new(0.0, 0.0, -2.0), Vec3::ZERO, 0.5);
+        physics.update_queries();
+        let everything = groups::LEVEL | groups::BALLS;
+        assert_eq!(
+            physics.intersect_point(Vec3::new(0.0, 0.0, -2.2), everything),
+            [3]
+        );
+
+        let sphere = rapier3d::prelude::Ball::new(5.5);
+        let mut ids = physics.intersect_shape(&sphere, Vec3::ZERO, Quat::IDENTITY, everything);
+        ids.sort_unstable();
+        assert_eq!(ids, [3, PANE_ID]);
+
+        // Parked balls are ignored
+        physics.bodies[ball].set_enabled(false);
+        assert!(
+            physics
+                .intersect_point(Vec3::new(0.0, 0.0, -2.2), everything)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn steps_keep_queries_current() {
+        let mut physics = Physics::with_settings(PhysicsSettings {
+            gravity: Vec3::ZERO,
+            ..Default::default()
+        });
+        physics.create_ball(3, Vec3::ZERO, Vec3::new(0.0, 0.0, -1.0), 0.1);
+        for _ in 0..60 {
+            physics.step(1.0 / 60.0);
+        }
+
+        let hit = physics
+            .cast_ray(Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z, 100.0, groups::BALLS)
+            .unwrap();
+        assert_eq!(hit.id, 3);
+        assert!((hit.point.z - (-0.9)).abs() < 1e-2);
+    }
 }
@@ -0,0 +1,311 @@
+use std::f32::consts::FRAC_PI_2;
+
+use glam::Vec3;
+use rapier3d::{
+    control::{CharacterLength, KinematicCharacterController},
+    math::Vector,
+    prelude::{
+        ColliderBuilder, ColliderHandle, InteractionGroups, RigidBodyBuilder, RigidBodyHandle,
+        SharedShape,
+    },
+};
+use serde::Deserialize;
+
+use crate::physics::{Physics, groups};
+
+/// Gap kept between the capsule and obstacles
+const SKIN: f32 = 0.01;
+
+/// Player body and ball budget, read from the `player` section of the level manifest
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct PlayerSettings {
+    /// Capsule radius around the camera
+    pub radius: f32,
+    /// Half of the capsule segment, the capsule is `2 * (half_height + radius)` tall
+    pub half_height: f32,
+    /// Balls at the start of the level
+    pub balls: u32,
+    /// Balls lost when running into an obstacle
+    pub hit_cost: u32,
+    /// Seconds after a hit during which further hits are ignored
+    pub hit_cooldown: f32,
+    /// How far in front of the player glass shatters on a hit
+    pub shatter_distance: f32,
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        Self {
+            radius: 0.25,
+            half_height: 0.25,
+            balls: 25,
+            hit_cost: 10,
+            hit_cooldown: 1.0,
+            shatter_distance: 2.0,
+        }
+    }
+}
+
+/// The player ran into an obstacle
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerHit {
+    /// Scene object id of the obstacle
+    pub id: u128,
+    pub position: Vec3,
+    /// Glass shattered in front of the player, as `(mesh_id, instance_index)`
+    /// in removal order
+    pub shattered: Vec<(u64, usize)>,
+}
+
+/// Kinematic capsule that follows the camera
+pub struct Player {
+    pub settings: PlayerSettings,
+    pub body: RigidBodyHandle,
+    pub collider: ColliderHandle,
+    pub balls: u32,
+    /// Slides the capsule along obstacles
+    controller: KinematicCharacterController,
+    /// Seconds left until the next hit counts
+    cooldown: f32,
+    /// The first move teleports, there is nothing to sweep from yet
+    placed: bool,
+}
+
+impl Player {
+    pub fn new(physics: &mut Physics, settings: PlayerSettings) -> Self {
+        let body = physics
+            .bodies
+            .insert(RigidBodyBuilder::kinematic_position_based().build());
+
+        // Only touches the level, balls are thrown from inside the capsule
+        let collider = physics.colliders.insert_with_parent(
+            ColliderBuilder::capsule_y(settings.half_height, settings.radius)
+                .collision_groups(InteractionGroups::new(groups::PLAYER, groups::LEVEL))
+                .build(),
+            body,
+            &mut physics.bodies,
+        );
+
+        Self {
+            settings,
+            body,
+            collider,
+            balls: settings.balls,
+            // The player flies, there is no ground to snap to or slopes
+            // too steep to climb
+            controller: KinematicCharacterController {
+                offset: CharacterLength::Absolute(SKIN),
+                max_slope_climb_angle: FRAC_PI_2,
+                min_slope_slide_angle: FRAC_PI_2,
+                snap_to_ground: None,
+                ..Default::default()
+            },
+            cooldown: 0.0,
+            placed: false,
+        }
+    }
+
+    /// Resets the ball budget and the capsule for a new level
+    pub fn apply_settings(&mut self, physics: &mut Physics, settings: PlayerSettings) {
+        self.settings = settings;
+        self.balls = settings.balls;
+        self.cooldown = 0.0;
+        self.placed = false;
+
+        if let Some(collider) = physics.colliders.get_mut(self.collider) {
+            collider.set_shape(SharedShape::capsule_y(
+                settings.half_height,
+                settings.radius,
+            ));
+        }
+    }
+
+    pub fn position(&self, physics: &Physics) -> Vec3 {
+        let translation = physics.bodies[self.body].translation();
+        Vec3::new(translation.x, translation.y, translation.z)
+    }
+
+    /// Takes a ball for a throw, `false` when there are none left
+    pub fn take_ball(&mut self) -> bool {
+        if self.balls == 0 {
+            return false;
+        }
+        self.balls -= 1;
+        true
+    }
+
+    /// Sweeps the capsule towards `target`, sliding along obstacles instead
+    /// of flying through them. Returns where it ended up and the obstacle it
+    /// ran into, hits during the cooldown return no obstacle.
+    pub fn move_to(
+        &mut self,
+        physics: &mut Physics,
+        target: Vec3,
+        dt: f32,
+    ) -> (Vec3, Option<u128>) {
+        self.cooldown = (self.cooldown - dt).max(0.0);
+
+        if !self.placed {
+            self.set_position(physics, target);
+            self.placed = true;
+            return (target, None);
+        }
+
+        let shape = physics.colliders[self.collider].shape();
+        let position = self.position(physics);
+        let (moved, obstacles) = physics.move_character(
+            &self.controller,
+            shape,
+            position,
+            target - position,
+            groups::LEVEL,
+            dt,
+        );
+        let position = position + moved;
+        let obstacle = obstacles.first().copied();
+
+        self.set_position(physics, position);
+
+        let hit = obstacle.filter(|_| self.cooldown == 0.0).inspect(|_| {
+            self.cooldown = self.settings.hit_cooldown;
+            self.balls = self.balls.saturating_sub(self.settings.hit_cost);
+        });
+
+        (position, hit)
+    }
+
+    /// Moves the capsule to `position` without sweeping or hits, for the
+    /// debug camera
+    pub fn teleport(&mut self, physics: &mut Physics, position: Vec3) {
+        self.set_position(physics, position);
+        self.placed = true;
+    }
+
+    fn set_position(&self, physics: &mut Physics, position: Vec3) {
+        let body = &mut physics.bodies[self.body];
+        let translation = Vector::new(position.x, position.y, position.z);
+        if self.placed {
+            body.set_next_kinematic_translation(translation);
+        } else {
+            body.set_translation(translation, true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat3, Mat4};
+
+    use crate::{
+        level::{LevelMeshes, hash_string_to_u64},
+        renderer::{pipeline::InstanceRaw, uniform::camera::CameraState},
+        world::World,
+    };
+
+    use super::*;
+
+    const PANE_Z: f32 = -3.0;
+
+    /// World with a single 4x4 pane across the way, standing at z = -3
+    fn world_with_pane(glass: bool) -> World {
+        let mut meshes = LevelMeshes::default();
+        meshes.colliders.insert(
+            "pane".to_string(),
+            (
+                vec![
+                    Vec3::new(-2.0, -2.0, 0.0),
+                    Vec3::new(2.0, -2.0, 0.0),
+                    Vec3::new(2.0, 2.0, 0.0),
+                    Vec3::new(-2.0, 2.0, 0.0),
+                ],
+                vec![0, 1, 2, 0, 2, 3],
+            ),
+        );
+        meshes.instances.insert(
+            "pane".to_string(),
+            vec![InstanceRaw {
+                model: Mat4::from_translation(Vec3::new(0.0, 0.0, PANE_Z)).to_cols_array_2d(),
+                normal: Mat3::IDENTITY.to_cols_array_2d(),
+            }],
+        );
+
+        let mut world = World::new();
+        world.add_colliders(&meshes);
+        if glass {
+            world.glass.insert(hash_string_to_u64("pane"));
+        }
+        world
+    }
+
+    /// Flies forward for two seconds, returns the final position and the hits
+    fn fly(world: &mut World) -> (Vec3, Vec<PlayerHit>) {
+        let dt = 1.0 / 60.0;
+        let mut hits = Vec::new();
+        let mut position = Vec3::ZERO;
+
+        for tick in 0..120 {
+            let target = Vec3::new(0.0, 0.0, -(tick as f32) * 0.1);
+            let (moved, hit) = world.move_player(target, Vec3::NEG_Z, dt);
+            position = moved;
+            hits.extend(hit);
+            world.tick(dt);
+        }
+
+        (position, hits)
+    }
+
+    #[test]
+    fn walls_stop_the_player() {
+        let mut world = world_with_pane(false);
+        let (position, hits) = fly(&mut world);
+
+        assert!(position.z > PANE_Z, "flew through the wall to {position}");
+        // Pressed against the wall, but the cooldown limits it to a hit a second
+        assert_eq!(hits.len(), 2);
+        assert!(hits.iter().all(|hit| hit.shattered.is_empty()));
+        assert_eq!(world.player.balls, 5);
+    }
+
+    #[test]
+    fn hits_shatter_glass() {
+        let mut world = world_with_pane(true);
+        let (position, hits) = fly(&mut world);
+
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].shattered, [(hash_string_to_u64("pane"), 0)]);
+        assert!(position.z < PANE_Z);
+        assert!(world.objects.is_empty());
+    }
+
+    #[test]
+    fn noclip_flies_through_walls() {
+        let mut world = world_with_pane(true);
+        let dt = 1.0 / 60.0;
+
+        for tick in 0..120 {
+            let camera = CameraState {
+                position: Vec3::new(0.0, 0.0, -(tick as f32) * 0.1),
+                yaw: -90.0f32.to_radians(),
+                pitch: 0.0,
+            };
+            let step = world.step(camera, true, dt);
+            assert!(step.hit.is_none(), "hit on tick {tick}");
+            assert_eq!(step.position, camera.position);
+        }
+
+        assert!(world.player.position(&world.physics).z < PANE_Z);
+        assert_eq!(world.player.balls, PlayerSettings::default().balls);
+        // Culled from the camera, past the pane
+        assert!(world.objects.is_empty());
+    }
+
+    #[test]
+    fn throws_need_balls() {
+        let mut world = World::new();
+        world.player.balls = 1;
+
+        assert!(world.throw_ball(Vec3::ZERO, Vec3::NEG_Z, 15.0).is_some());
+        assert!(world.throw_ball(Vec3::ZERO, Vec3::NEG_Z, 15.0).is_none());
+    }
+}
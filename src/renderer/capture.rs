@@ -0,0 +1,350 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, SyncSender},
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use image::RgbaImage;
+
+use crate::renderer::offscreen;
+
+/// Readback buffers in flight at most, the render loop waits for the
+/// oldest one beyond that
+const MAX_BUFFERS: usize = 3;
+/// Images waiting for the PNG encoder at most, sending blocks beyond that
+const MAX_QUEUED_IMAGES: usize = 2;
+
+struct PendingFrame {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    bgra: bool,
+    path: PathBuf,
+    mapping: bool,
+    ready: Arc<AtomicBool>,
+}
+
+/// Copy of the output the post stack writes when a capture is due. Surfaces
+/// can't always be copied from, this one always can.
+struct CaptureTarget {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+/// Dumps every frame to `directory`, for trailer footage
+pub struct FrameSequence {
+    pub directory: PathBuf,
+    /// Simulated frame rate, the game advances exactly `1 / fps` per frame
+    pub fps: f32,
+    pub frame: u32,
+}
+
+/// Copies finished frames into readback buffers and writes them as PNG on
+/// a worker thread. The render loop only waits for the GPU or the disk when
+/// captures pile up faster than they are written.
+pub struct Capture {
+    pub directory: PathBuf,
+    pub sequence: Option<FrameSequence>,
+    screenshot_requested: bool,
+    target: Option<CaptureTarget>,
+    /// Where the frame prepared with [Capture::prepare] goes
+    due: Option<PathBuf>,
+    pending: Vec<PendingFrame>,
+    /// Readback buffers to reuse, all of the current target size
+    free: Vec<wgpu::Buffer>,
+    writer: SyncSender<(PathBuf, RgbaImage)>,
+}
+
+impl Capture {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        let (writer, receiver) = mpsc::sync_channel::<(PathBuf, RgbaImage)>(MAX_QUEUED_IMAGES);
+
+        thread::spawn(move || {
+            for (path, image) in receiver {
+                if let Some(parent) = path.parent()
+                    && let Err(e) = std::fs::create_dir_all(parent)
+                {
+                    log::error!("Failed to create {}: {e}", parent.display());
+                    continue;
+                }
+
+                match image.save(&path) {
+                    Ok(()) => log::info!("Saved {}", path.display()),
+                    Err(e) => log::error!("Failed to save {}: {e}", path.display()),
+                }
+            }
+        });
+
+        Self {
+            directory: directory.into(),
+            sequence: None,
+            screenshot_requested: false,
+            target: None,
+            due: None,
+            pending: Vec::new(),
+            free: Vec::new(),
+            writer,
+        }
+    }
+
+    /// Captures the next rendered frame
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    pub fn start_sequence(&mut self, fps: f32) {
+        let directory = self.directory.join(format!("sequence_{}", timestamp()));
+        log::info!("Recording frames to {}", directory.display());
+
+        self.sequence = Some(FrameSequence {
+            directory,
+            fps,
+            frame: 0,
+        });
+    }
+
+    pub fn stop_sequence(&mut self) {
+        if let Some(sequence) = self.sequence.take() {
+            log::info!("Recorded {} frames", sequence.frame);
+        }
+    }
+
+    pub fn toggle_sequence(&mut self, fps: f32) {
+        if self.sequence.is_some() {
+            self.stop_sequence();
+        } else {
+            self.start_sequence(fps);
+        }
+    }
+
+    /// Fixed frame time while a sequence is recorded
+    pub fn sequence_frame_time(&self) -> Option<f32> {
+        self.sequence.as_ref().map(|sequence| 1.0 / sequence.fps)
+    }
+
+    fn next_path(&mut self) -> Option<PathBuf> {
+        if let Some(sequence) = &mut self.sequence {
+            let path = sequence
+                .directory
+                .join(format!("frame_{:06}.png", sequence.frame));
+            sequence.frame += 1;
+            return Some(path);
+        }
+
+        if std::mem::take(&mut self.screenshot_requested) {
+            return Some(
+                self.directory
+                    .join(format!("screenshot_{}.png", timestamp())),
+            );
+        }
+
+        None
+    }
+
+    /// Returns the view the frame has to be drawn to as well when a
+    /// capture is due, matching an output of `width` x `height` in `format`
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Option<wgpu::TextureView> {
+        self.due = Some(self.next_path()?);
+
+        let texture = self.target.as_ref().map(|target| &target.texture);
+        if texture.is_none_or(|texture| {
+            (texture.width(), texture.height(), texture.format()) != (width, height, format)
+        }) {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Capture::target"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            self.target = Some(CaptureTarget { texture, view });
+            self.free.clear();
+        }
+
+        self.target.as_ref().map(|target| target.view.clone())
+    }
+
+    /// Adds a copy of the frame drawn to the view from [Capture::prepare]
+    /// to the encoder
+    pub fn record(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let (Some(path), Some(target)) = (self.due.take(), &self.target) else {
+            return;
+        };
+        let texture = target.texture.clone();
+
+        if self.pending.len() >= MAX_BUFFERS {
+            self.collect(device, wgpu::PollType::Wait);
+        }
+
+        let (width, height) = (texture.width(), texture.height());
+        let buffer = self
+            .free
+            .pop()
+            .unwrap_or_else(|| offscreen::create_readback_buffer(device, width, height));
+        offscreen::copy_texture_to_buffer(encoder, &texture, &buffer, width, height);
+
+        self.pending.push(PendingFrame {
+            buffer,
+            width,
+            height,
+            bgra: matches!(
+                texture.format(),
+                wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+            ),
+            path,
+            mapping: false,
+            ready: Arc::new(AtomicBool::new(false)),
+        });
+    }
+
+    /// Must be called after the encoder passed to [Capture::record] was
+    /// submitted. Hands the frames the GPU is done with to the writer thread.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        if !self.pending.is_empty() {
+            self.collect(device, wgpu::PollType::Poll);
+        }
+    }
+
+    fn collect(&mut self, device: &wgpu::Device, poll: wgpu::PollType) {
+        for frame in self.pending.iter_mut().filter(|frame| !frame.mapping) {
+            let ready = frame.ready.clone();
+            frame
+                .buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| match result {
+                    Ok(()) => ready.store(true, Ordering::Release),
+                    Err(e) => log::error!("Failed to map capture buffer: {e}"),
+                });
+            frame.mapping = true;
+        }
+
+        if let Err(e) = device.poll(poll) {
+            log::error!("Failed to poll device: {e}");
+        }
+
+        let (ready, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|frame| frame.ready.load(Ordering::Acquire));
+        self.pending = pending;
+
+        for frame in ready {
+            let image = offscreen::buffer_to_image(
+                &frame.buffer.slice(..).get_mapped_range(),
+                frame.width,
+                frame.height,
+                frame.bgra,
+            );
+            frame.buffer.unmap();
+
+            // Buffers of an older target size are dropped
+            let size = self.target.as_ref().map(|target| &target.texture);
+            if size.is_some_and(|texture| {
+                (texture.width(), texture.height()) == (frame.width, frame.height)
+            }) {
+                self.free.push(frame.buffer);
+            }
+
+            match image {
+                // Blocks while the writer is behind
+                Ok(image) => {
+                    let _ = self.writer.send((frame.path, image));
+                }
+                Err(e) => log::error!("Failed to read capture: {e}"),
+            }
+        }
+    }
+}
+
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}_{:03}", now.as_secs(), now.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::renderer::Renderer;
+
+    use super::*;
+
+    fn renderer(directory: &str) -> Option<Renderer> {
+        match pollster::block_on(Renderer::new_offscreen(64, 64, false)) {
+            Ok(mut renderer) => {
+                renderer.capture = Capture::new(std::env::temp_dir().join(directory));
+                Some(renderer)
+            }
+            Err(e) => {
+                eprintln!("Skipping capture test: {e}");
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn screenshot_is_written() {
+        let Some(mut renderer) = renderer("smashbit_capture_test") else {
+            return;
+        };
+        let _ = std::fs::remove_dir_all(&renderer.capture.directory);
+
+        renderer.capture.request_screenshot();
+        renderer.render().unwrap();
+
+        // Waits until the writer thread saved a complete file
+        let start = Instant::now();
+        let image = loop {
+            renderer
+                .capture
+                .collect(&renderer.device, wgpu::PollType::Wait);
+            let written = std::fs::read_dir(&renderer.capture.directory)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .find_map(|entry| image::open(entry.path()).ok());
+            if let Some(image) = written {
+                break image;
+            }
+            assert!(start.elapsed() < Duration::from_secs(10), "nothing written");
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        assert_eq!(image.to_rgba8().dimensions(), (64, 64));
+    }
+
+    #[test]
+    fn sequences_reuse_buffers() {
+        let Some(mut renderer) = renderer("smashbit_sequence_test") else {
+            return;
+        };
+
+        renderer.capture.start_sequence(30.0);
+        for _ in 0..10 {
+            renderer.render().unwrap();
+            let capture = &renderer.capture;
+            assert!(capture.pending.len() + capture.free.len() <= MAX_BUFFERS);
+        }
+        renderer.capture.stop_sequence();
+    }
+}
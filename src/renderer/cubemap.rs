@@ -0,0 +1,374 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result, bail, ensure};
+use ddsfile::{Caps2, D3DFormat, Dds, DxgiFormat, MiscFlag};
+use wgpu::{TextureFormat, util::DeviceExt};
+
+/// Faces of a cubemap in layer order
+pub const FACES: [&str; 6] = ["+X", "-X", "+Y", "-Y", "+Z", "-Z"];
+
+/// Cubemap pixels read from disk, all six faces with their mips. This is
+/// plain CPU data like [LevelMeshes](crate::level::LevelMeshes), so files
+/// can be checked without a GPU.
+#[derive(Debug)]
+pub struct CubemapData {
+    pub format: TextureFormat,
+    /// Width and height of a face
+    pub size: u32,
+    pub mip_level_count: u32,
+    /// KTX2 stores all faces of a mip together, DDS all mips of a face
+    pub order: wgpu::util::TextureDataOrder,
+    pub data: Vec<u8>,
+}
+
+impl CubemapData {
+    /// Reads a KTX2 or DDS file, picked by its extension
+    pub fn load(path: &str) -> Result<Self> {
+        log::info!("Loading cubemap {path}");
+
+        let bytes = fs::read(path).with_context(|| format!("Failed to read cubemap {path}"))?;
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+
+        match extension.as_deref() {
+            Some("ktx2") => Self::from_ktx2(&bytes),
+            Some("dds") => Self::from_dds(&bytes),
+            _ => bail!("Cubemap {path} is neither KTX2 nor DDS"),
+        }
+        .with_context(|| format!("Failed to load cubemap {path}"))
+    }
+
+    /// Reads six images of the same square size, in [FACES] order
+    pub fn load_faces(paths: &[String; 6]) -> Result<Self> {
+        log::info!("Loading cubemap faces {paths:?}");
+
+        let mut faces = Vec::with_capacity(6);
+        for path in paths {
+            faces.push(image::open(path).with_context(|| format!("Failed to load {path}"))?);
+        }
+        Self::from_images(&faces)
+    }
+
+    pub fn from_images(faces: &[image::DynamicImage]) -> Result<Self> {
+        ensure!(
+            faces.len() == 6,
+            "A cubemap needs 6 faces, got {}",
+            faces.len()
+        );
+
+        let size = faces[0].width();
+        let mut data = Vec::with_capacity((size * size * 4 * 6) as usize);
+        for (face, name) in faces.iter().zip(FACES) {
+            ensure!(
+                face.width() == size && face.height() == size,
+                "Face {name} is {}x{}, all faces must be {size}x{size}",
+                face.width(),
+                face.height()
+            );
+            data.extend_from_slice(&face.to_rgba8());
+        }
+
+        Ok(Self {
+            format: TextureFormat::Rgba8UnormSrgb,
+            size,
+            mip_level_count: 1,
+            order: wgpu::util::TextureDataOrder::LayerMajor,
+            data,
+        })
+    }
+
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self> {
+        let reader = ktx2::Reader::new(bytes)?;
+        let header = reader.header();
+
+        ensure!(
+            header.supercompression_scheme.is_none(),
+            "Supercompressed KTX2 files are not supported"
+        );
+        ensure!(
+            header.face_count == 6,
+            "Expected 6 faces, got {}",
+            header.face_count
+        );
+        ensure!(
+            header.layer_count <= 1 && header.pixel_depth <= 1,
+            "Cubemap arrays and 3D textures are not supported"
+        );
+
+        let format = header
+            .format
+            .and_then(ktx2_format)
+            .with_context(|| format!("Unsupported KTX2 format {:?}", header.format))?;
+
+        let data: Vec<u8> = reader
+            .levels()
+            .flat_map(|level| level.data.iter().copied())
+            .collect();
+
+        Self::new(
+            format,
+            header.pixel_width,
+            header.pixel_height,
+            header.level_count.max(1),
+            wgpu::util::TextureDataOrder::MipMajor,
+            data,
+        )
+    }
+
+    pub fn from_dds(bytes: &[u8]) -> Result<Self> {
+        let dds = Dds::read(bytes)?;
+
+        let cubemap = dds.header.caps2.contains(Caps2::CUBEMAP)
+            || dds
+                .header10
+                .as_ref()
+                .is_some_and(|header| header.misc_flag.contains(MiscFlag::TEXTURECUBE));
+        ensure!(cubemap, "The DDS file is not a cubemap");
+
+        let format = match (dds.get_dxgi_format(), dds.get_d3d_format()) {
+            (Some(format), _) => dxgi_format(format),
+            (None, Some(format)) => d3d_format(format),
+            (None, None) => None,
+        }
+        .with_context(|| {
+            format!(
+                "Unsupported DDS format, DXGI {:?}, D3D {:?}",
+                dds.get_dxgi_format(),
+                dds.get_d3d_format()
+            )
+        })?;
+
+        Self::new(
+            format,
+            dds.get_width(),
+            dds.get_height(),
+            dds.get_num_mipmap_levels().max(1),
+            wgpu::util::TextureDataOrder::LayerMajor,
+            dds.data,
+        )
+    }
+
+    /// Checks that `data` holds all faces and mips, extra bytes are dropped
+    fn new(
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+        mip_level_count: u32,
+        order: wgpu::util::TextureDataOrder,
+        mut data: Vec<u8>,
+    ) -> Result<Self> {
+        ensure!(
+            width == height && width > 0,
+            "Faces must be square, got {width}x{height}"
+        );
+        ensure!(
+            mip_level_count <= width.ilog2() + 1,
+            "{mip_level_count} mips is too many for {width}x{height} faces"
+        );
+
+        let expected = Self::byte_size(format, width, mip_level_count);
+        ensure!(
+            data.len() >= expected,
+            "Expected {expected} bytes of pixels, got {}",
+            data.len()
+        );
+        data.truncate(expected);
+
+        Ok(Self {
+            format,
+            size: width,
+            mip_level_count,
+            order,
+            data,
+        })
+    }
+
+    /// Bytes of all faces and mips of a `size` cubemap
+    fn byte_size(format: TextureFormat, size: u32, mip_level_count: u32) -> usize {
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format.block_copy_size(None).unwrap_or(0);
+
+        (0..mip_level_count)
+            .map(|mip| {
+                let size = (size >> mip).max(1);
+                (size.div_ceil(block_width) * size.div_ceil(block_height) * block_size) as usize
+            })
+            .sum::<usize>()
+            * 6
+    }
+}
+
+/// Cubemap texture, sampled with a direction
+pub struct Cubemap {
+    #[allow(unused)]
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl Cubemap {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, data: &CubemapData) -> Result<Self> {
+        let features = data.format.guaranteed_format_features(device.features());
+        ensure!(
+            device.features().contains(data.format.required_features())
+                && features
+                    .flags
+                    .contains(wgpu::TextureFormatFeatureFlags::FILTERABLE),
+            "{:?} textures are not supported by this GPU",
+            data.format
+        );
+
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Cubemap"),
+                size: wgpu::Extent3d {
+                    width: data.size,
+                    height: data.size,
+                    depth_or_array_layers: 6,
+                },
+                mip_level_count: data.mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: data.format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            data.order,
+            &data.data,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Cubemap view"),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+
+        Ok(Self { texture, view })
+    }
+
+    /// Black 1x1 cubemap, bound while nothing else is
+    pub fn placeholder(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let data = CubemapData {
+            format: TextureFormat::Rgba8UnormSrgb,
+            size: 1,
+            mip_level_count: 1,
+            order: wgpu::util::TextureDataOrder::LayerMajor,
+            data: vec![0; 4 * 6],
+        };
+        Self::new(device, queue, &data).expect("Rgba8UnormSrgb is always supported")
+    }
+}
+
+fn ktx2_format(format: ktx2::Format) -> Option<TextureFormat> {
+    use ktx2::Format;
+
+    Some(match format {
+        Format::R8G8B8A8_UNORM => TextureFormat::Rgba8Unorm,
+        Format::R8G8B8A8_SRGB => TextureFormat::Rgba8UnormSrgb,
+        Format::B8G8R8A8_UNORM => TextureFormat::Bgra8Unorm,
+        Format::B8G8R8A8_SRGB => TextureFormat::Bgra8UnormSrgb,
+        Format::R16G16B16A16_SFLOAT => TextureFormat::Rgba16Float,
+        Format::R32G32B32A32_SFLOAT => TextureFormat::Rgba32Float,
+        Format::BC1_RGBA_UNORM_BLOCK => TextureFormat::Bc1RgbaUnorm,
+        Format::BC1_RGBA_SRGB_BLOCK => TextureFormat::Bc1RgbaUnormSrgb,
+        Format::BC3_UNORM_BLOCK => TextureFormat::Bc3RgbaUnorm,
+        Format::BC3_SRGB_BLOCK => TextureFormat::Bc3RgbaUnormSrgb,
+        Format::BC6H_UFLOAT_BLOCK => TextureFormat::Bc6hRgbUfloat,
+        Format::BC6H_SFLOAT_BLOCK => TextureFormat::Bc6hRgbFloat,
+        Format::BC7_UNORM_BLOCK => TextureFormat::Bc7RgbaUnorm,
+        Format::BC7_SRGB_BLOCK => TextureFormat::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+fn dxgi_format(format: DxgiFormat) -> Option<TextureFormat> {
+    Some(match format {
+        DxgiFormat::R8G8B8A8_UNorm => TextureFormat::Rgba8Unorm,
+        DxgiFormat::R8G8B8A8_UNorm_sRGB => TextureFormat::Rgba8UnormSrgb,
+        DxgiFormat::B8G8R8A8_UNorm => TextureFormat::Bgra8Unorm,
+        DxgiFormat::B8G8R8A8_UNorm_sRGB => TextureFormat::Bgra8UnormSrgb,
+        DxgiFormat::R16G16B16A16_Float => TextureFormat::Rgba16Float,
+        DxgiFormat::R32G32B32A32_Float => TextureFormat::Rgba32Float,
+        DxgiFormat::BC1_UNorm => TextureFormat::Bc1RgbaUnorm,
+        DxgiFormat::BC1_UNorm_sRGB => TextureFormat::Bc1RgbaUnormSrgb,
+        DxgiFormat::BC3_UNorm => TextureFormat::Bc3RgbaUnorm,
+        DxgiFormat::BC3_UNorm_sRGB => TextureFormat::Bc3RgbaUnormSrgb,
+        DxgiFormat::BC6H_UF16 => TextureFormat::Bc6hRgbUfloat,
+        DxgiFormat::BC6H_SF16 => TextureFormat::Bc6hRgbFloat,
+        DxgiFormat::BC7_UNorm => TextureFormat::Bc7RgbaUnorm,
+        DxgiFormat::BC7_UNorm_sRGB => TextureFormat::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+/// Formats of DDS files without the DX10 header
+fn d3d_format(format: D3DFormat) -> Option<TextureFormat> {
+    Some(match format {
+        D3DFormat::A8B8G8R8 => TextureFormat::Rgba8Unorm,
+        D3DFormat::A8R8G8B8 => TextureFormat::Bgra8Unorm,
+        D3DFormat::A16B16G16R16F => TextureFormat::Rgba16Float,
+        D3DFormat::A32B32G32R32F => TextureFormat::Rgba32Float,
+        D3DFormat::DXT1 => TextureFormat::Bc1RgbaUnorm,
+        D3DFormat::DXT5 => TextureFormat::Bc3RgbaUnorm,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ddsfile::{AlphaMode, D3D10ResourceDimension, NewDxgiParams};
+    use image::{DynamicImage, RgbaImage};
+
+    use super::*;
+
+    fn dds_cubemap(format: DxgiFormat, size: u32, mips: u32) -> Vec<u8> {
+        let dds = Dds::new_dxgi(NewDxgiParams {
+            height: size,
+            width: size,
+            depth: None,
+            format,
+            mipmap_levels: Some(mips),
+            array_layers: Some(6),
+            caps2: Some(Caps2::CUBEMAP | Caps2::CUBEMAP_ALLFACES),
+            is_cubemap: true,
+            resource_dimension: D3D10ResourceDimension::Texture2D,
+            alpha_mode: AlphaMode::Straight,
+        })
+        .unwrap();
+
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn reads_dds_cubemaps() {
+        let data =
+            CubemapData::from_dds(&dds_cubemap(DxgiFormat::R8G8B8A8_UNorm_sRGB, 8, 4)).unwrap();
+        assert_eq!(data.format, TextureFormat::Rgba8UnormSrgb);
+        assert_eq!((data.size, data.mip_level_count), (8, 4));
+        assert_eq!(data.data.len(), (64 + 16 + 4 + 1) * 4 * 6);
+
+        // Blocks are 4x4, the smallest mips still take a whole one
+        let data = CubemapData::from_dds(&dds_cubemap(DxgiFormat::BC7_UNorm, 8, 4)).unwrap();
+        assert_eq!(data.format, TextureFormat::Bc7RgbaUnorm);
+        assert_eq!(data.data.len(), (4 + 1 + 1 + 1) * 16 * 6);
+
+        assert!(CubemapData::from_dds(&dds_cubemap(DxgiFormat::R8_UNorm, 8, 1)).is_err());
+    }
+
+    #[test]
+    fn faces_must_match() {
+        let face = |size| DynamicImage::ImageRgba8(RgbaImage::new(size, size));
+
+        let data = CubemapData::from_images(&[0; 6].map(|_| face(4))).unwrap();
+        assert_eq!(data.data.len(), 4 * 4 * 4 * 6);
+
+        let mut faces = [0; 6].map(|_| face(4));
+        faces[3] = face(8);
+        assert!(CubemapData::from_images(&faces).is_err());
+        assert!(CubemapData::from_images(&faces[..5]).is_err());
+    }
+}
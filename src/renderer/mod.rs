@@ -1,32 +1,70 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use glam::Vec3;
-use std::sync::Arc;
+use image::RgbaImage;
+use std::{sync::Arc, time::Instant};
 use wgpu::Trace;
 use winit::{dpi::PhysicalSize, window::Window};
 
-use crate::renderer::{pipeline::Pipelines, uniform::Uniforms};
-
+use crate::renderer::{
+    capture::Capture,
+    offscreen::OffscreenTarget,
+    pipeline::{Pipelines, hdr::HdrPipeline},
+    resolution::{DynamicResolution, RENDER_SCALE_RANGE},
+    settings::RendererSettings,
+    timer::GpuTimer,
+    uniform::Uniforms,
+};
+
+pub mod capture;
+pub mod cubemap;
 pub mod mesh;
+pub mod offscreen;
 pub mod pipeline;
+pub mod resolution;
+pub mod settings;
 pub mod texture;
+pub mod timer;
 pub mod uniform;
 
-pub struct Renderer {
-    pub window: Arc<Window>,
+/// Where the tone mapped frame ends up
+pub enum RenderTarget {
+    Surface {
+        window: Arc<Window>,
+        surface: wgpu::Surface<'static>,
+        config: wgpu::SurfaceConfiguration,
+        capabilities: wgpu::SurfaceCapabilities,
+    },
+    Offscreen(OffscreenTarget),
+}
 
-    pub surface: wgpu::Surface<'static>,
-    pub surface_config: wgpu::SurfaceConfiguration,
+pub struct Renderer {
+    pub target: RenderTarget,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
+    settings: RendererSettings,
+
+    /// MSAA samples of the scene pass, one of `supported_sample_counts`
+    pub sample_count: u32,
+    pub supported_sample_counts: Vec<u32>,
+
+    /// Resolution of the scene relative to the output, within
+    /// [RENDER_SCALE_RANGE]
+    pub render_scale: f32,
+    /// Adjusts `render_scale` every frame when set
+    pub dynamic_resolution: Option<DynamicResolution>,
+    /// `None` without timestamp queries, frame times are measured on the
+    /// CPU then
+    timer: Option<GpuTimer>,
 
     pub depth_texture: texture::Texture,
 
     pub uniforms: Uniforms,
     pub pipelines: Pipelines,
+    pub capture: Capture,
 }
 
 impl Renderer {
-    pub async fn new(window: Arc<Window>) -> Result<Self> {
+    pub async fn new(window: Arc<Window>, settings: RendererSettings) -> Result<Self> {
         log::info!("Creating renderer...");
 
         let size = window.inner_size();
@@ -50,17 +88,7 @@ impl Renderer {
             })
             .await?;
 
-        log::info!("Requesting device & queue");
-
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: Some("Device & Queue"),
-                required_features: wgpu::Features::empty(),
-                required_limits: wgpu::Limits::default(),
-                memory_hints: wgpu::MemoryHints::default(),
-                trace: Trace::Off,
-            })
-            .await?;
+        let (device, queue) = Self::request_device(&adapter, wgpu::Limits::default()).await?;
 
         log::info!("Getting possible texture format");
 
@@ -77,72 +105,477 @@ impl Renderer {
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
+            present_mode: settings.present_mode(&surface_caps.present_modes),
+            alpha_mode: Self::alpha_mode(&surface_caps.alpha_modes),
             view_formats: vec![],
-            desired_maximum_frame_latency: 2,
+            desired_maximum_frame_latency: settings.max_frame_latency,
         };
 
         log::info!("Configuring surface");
 
         surface.configure(&device, &surface_config);
 
+        Ok(Self::with_target(
+            &adapter,
+            device,
+            queue,
+            size,
+            RenderTarget::Surface {
+                window,
+                surface,
+                config: surface_config,
+                capabilities: surface_caps,
+            },
+            settings,
+        ))
+    }
+
+    /// Creates a renderer without a window that draws into a texture.
+    /// Falls back to a software adapter when no hardware one is available,
+    /// `force_fallback` skips the hardware one entirely. MSAA starts disabled,
+    /// so golden images don't depend on the rasterizer's sample pattern.
+    pub async fn new_offscreen(width: u32, height: u32, force_fallback: bool) -> Result<Self> {
+        log::info!("Creating offscreen renderer...");
+
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::from_env().unwrap_or(wgpu::Backends::all()),
+            ..Default::default()
+        });
+
+        log::info!("Requesting adapter");
+
+        let mut adapter = None;
+        for fallback in [force_fallback, true] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: None,
+                    force_fallback_adapter: fallback,
+                })
+                .await
+                .ok();
+
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let Some(adapter) = adapter else {
+            bail!("No adapter available for offscreen rendering");
+        };
+        log::info!("Using adapter {:?}", adapter.get_info());
+
+        // Software adapters often can't do the full default limits
+        let limits = wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits());
+        let (device, queue) = Self::request_device(&adapter, limits).await?;
+        let target = OffscreenTarget::new(&device, width, height);
+
+        Ok(Self::with_target(
+            &adapter,
+            device,
+            queue,
+            PhysicalSize::new(width, height),
+            RenderTarget::Offscreen(target),
+            RendererSettings {
+                msaa: 1,
+                ..Default::default()
+            },
+        ))
+    }
+
+    /// The frame is opaque, anything else would let the desktop shine through
+    fn alpha_mode(supported: &[wgpu::CompositeAlphaMode]) -> wgpu::CompositeAlphaMode {
+        [
+            wgpu::CompositeAlphaMode::Opaque,
+            wgpu::CompositeAlphaMode::Inherit,
+        ]
+        .into_iter()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(wgpu::CompositeAlphaMode::Auto)
+    }
+
+    async fn request_device(
+        adapter: &wgpu::Adapter,
+        required_limits: wgpu::Limits,
+    ) -> Result<(wgpu::Device, wgpu::Queue)> {
+        log::info!("Requesting device & queue");
+
+        Ok(adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("Device & Queue"),
+                // Allows MSAA sample counts besides 1 and 4 and GPU frame
+                // timing where the adapter has them
+                required_features: adapter.features()
+                    & (wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                        | GpuTimer::FEATURES),
+                required_limits,
+                memory_hints: wgpu::MemoryHints::default(),
+                trace: Trace::Off,
+            })
+            .await?)
+    }
+
+    fn with_target(
+        adapter: &wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        size: PhysicalSize<u32>,
+        target: RenderTarget,
+        settings: RendererSettings,
+    ) -> Self {
+        let supported_sample_counts = Self::find_sample_counts(adapter, &device);
+        let sample_count = Self::closest_sample_count(&supported_sample_counts, settings.msaa);
+        log::info!("Using {sample_count}x MSAA, supported: {supported_sample_counts:?}");
+
         let depth_texture = texture::Texture::create_depth_texture(
             &device,
             size.width,
             size.height,
+            sample_count,
             "depth_texture",
         );
 
+        let output_format = match &target {
+            RenderTarget::Surface { config, .. } => config.format,
+            RenderTarget::Offscreen(_) => OffscreenTarget::FORMAT,
+        };
+
         let uniforms = Uniforms::new(&device, &size);
 
-        Ok(Self {
-            pipelines: Pipelines::new(&device, &size, &uniforms.bind_group_layout),
+        let mut renderer = Self {
+            pipelines: Pipelines::new(
+                &device,
+                &queue,
+                &size,
+                &uniforms.bind_group_layout,
+                output_format,
+                sample_count,
+            ),
+            sample_count,
+            supported_sample_counts,
+            render_scale: 1.0,
+            dynamic_resolution: None,
+            timer: GpuTimer::new(&device, &queue),
             uniforms,
             depth_texture,
-            window,
-            surface,
-            surface_config,
+            capture: Capture::new("captures"),
+            target,
             device,
             queue,
-        })
+            settings: settings.clone(),
+        };
+        renderer.set_render_scale(settings.render_scale);
+        renderer.dynamic_resolution = settings.dynamic_resolution.map(DynamicResolution::new);
+        renderer
     }
 
-    pub fn resize(&mut self, new_size: &PhysicalSize<u32>) {
-        log::info!("Resizing window");
-        self.pipelines.resize(&self.device, new_size);
-        self.uniforms.resize(new_size);
-        (self.surface_config.width, self.surface_config.height) = (new_size.width, new_size.height);
-        self.surface.configure(&self.device, &self.surface_config);
+    /// Highest supported sample count up to `requested`
+    fn closest_sample_count(supported: &[u32], requested: u32) -> u32 {
+        supported
+            .iter()
+            .copied()
+            .filter(|&count| count <= requested)
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// Sample counts both the HDR and the depth format can be rendered with
+    fn find_sample_counts(adapter: &wgpu::Adapter, device: &wgpu::Device) -> Vec<u32> {
+        let adapter_specific = device
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+
+        [1, 2, 4, 8]
+            .into_iter()
+            .filter(|&count| {
+                // Only 1 and 4 are guaranteed without adapter specific features
+                (adapter_specific || count == 1 || count == 4)
+                    && [HdrPipeline::FORMAT, texture::Texture::DEPTH_FORMAT]
+                        .into_iter()
+                        .all(|format| {
+                            adapter
+                                .get_texture_format_features(format)
+                                .flags
+                                .sample_count_supported(count)
+                        })
+            })
+            .collect()
+    }
 
+    /// Switches MSAA, rebuilding the scene pipelines and attachments
+    pub fn set_sample_count(&mut self, sample_count: u32) -> Result<()> {
+        if !self.supported_sample_counts.contains(&sample_count) {
+            bail!(
+                "{sample_count}x MSAA is not supported, use one of {:?}",
+                self.supported_sample_counts
+            );
+        }
+        if sample_count == self.sample_count {
+            return Ok(());
+        }
+
+        log::info!("Switching to {sample_count}x MSAA");
+        self.sample_count = sample_count;
+        self.pipelines.set_sample_count(
+            &self.device,
+            &self.uniforms.bind_group_layout,
+            sample_count,
+        );
         self.depth_texture = texture::Texture::create_depth_texture(
             &self.device,
-            new_size.width,
-            new_size.height,
+            self.depth_texture.texture.width(),
+            self.depth_texture.texture.height(),
+            sample_count,
             "depth_texture",
         );
+
+        Ok(())
+    }
+
+    pub fn window(&self) -> Option<&Arc<Window>> {
+        match &self.target {
+            RenderTarget::Surface { window, .. } => Some(window),
+            RenderTarget::Offscreen(_) => None,
+        }
+    }
+
+    pub fn settings(&self) -> &RendererSettings {
+        &self.settings
     }
 
-    pub fn render(&self) -> Result<()> {
+    /// Applies `settings` to the surface and the scene targets, without
+    /// recreating the renderer
+    pub fn set_settings(&mut self, settings: RendererSettings) {
+        let sample_count = Self::closest_sample_count(&self.supported_sample_counts, settings.msaa);
+        if let Err(e) = self.set_sample_count(sample_count) {
+            log::warn!("{e}");
+        }
+
+        if settings.dynamic_resolution != self.settings.dynamic_resolution {
+            self.dynamic_resolution = settings.dynamic_resolution.map(DynamicResolution::new);
+        }
+        // The dynamic resolution picks its own scale
+        if settings.render_scale != self.settings.render_scale || self.dynamic_resolution.is_none()
+        {
+            self.set_render_scale(settings.render_scale);
+        }
+
+        if let RenderTarget::Surface {
+            surface,
+            config,
+            capabilities,
+            ..
+        } = &mut self.target
+        {
+            let present_mode = settings.present_mode(&capabilities.present_modes);
+            let max_frame_latency = settings.max_frame_latency.max(1);
+
+            if (config.present_mode, config.desired_maximum_frame_latency)
+                != (present_mode, max_frame_latency)
+            {
+                log::info!("Presenting with {present_mode:?}, {max_frame_latency} frames latency");
+                config.present_mode = present_mode;
+                config.desired_maximum_frame_latency = max_frame_latency;
+                surface.configure(&self.device, config);
+            }
+        }
+
+        let camera = &mut self.uniforms.camera;
+        if camera.projection.fovy != settings.fov {
+            camera.projection.fovy = settings.fov;
+            camera.update_uniform();
+        }
+
+        self.settings = settings;
+    }
+
+    /// Size of the frames after post-processing
+    pub fn output_size(&self) -> PhysicalSize<u32> {
+        match &self.target {
+            RenderTarget::Surface { config, .. } => PhysicalSize::new(config.width, config.height),
+            RenderTarget::Offscreen(target) => PhysicalSize::new(target.width, target.height),
+        }
+    }
+
+    /// Size the scene is rendered at, the output size times `render_scale`
+    pub fn scene_size(&self) -> PhysicalSize<u32> {
+        let output = self.output_size();
+        let (width, height) = resolution::scaled_size(
+            output.width,
+            output.height,
+            self.render_scale,
+            self.device.limits().max_texture_dimension_2d,
+        );
+        PhysicalSize::new(width, height)
+    }
+
+    /// Changes the render scale, clamped to [RENDER_SCALE_RANGE]
+    pub fn set_render_scale(&mut self, scale: f32) {
+        let scale = scale.clamp(*RENDER_SCALE_RANGE.start(), *RENDER_SCALE_RANGE.end());
+        if scale == self.render_scale {
+            return;
+        }
+
+        log::debug!("Render scale {scale}");
+        self.render_scale = scale;
+        self.resize_scene();
+    }
+
+    /// Recreates the targets that follow the scene size
+    fn resize_scene(&mut self) {
+        let size = self.scene_size();
+        self.pipelines
+            .resize(&self.device, &size, &self.output_size());
+
+        self.depth_texture = texture::Texture::create_depth_texture(
+            &self.device,
+            size.width,
+            size.height,
+            self.sample_count,
+            "depth_texture",
+        );
+    }
+
+    pub fn resize(&mut self, new_size: &PhysicalSize<u32>) {
+        log::info!("Resizing window");
+        self.uniforms.resize(new_size);
+
+        match &mut self.target {
+            RenderTarget::Surface {
+                surface, config, ..
+            } => {
+                (config.width, config.height) = (new_size.width, new_size.height);
+                surface.configure(&self.device, config);
+            }
+            RenderTarget::Offscreen(target) => {
+                *target = OffscreenTarget::new(&self.device, new_size.width, new_size.height);
+            }
+        }
+
+        self.resize_scene();
+    }
+
+    pub fn render(&mut self) -> Result<()> {
+        self.pipelines.particle_pipeline.update(&self.queue);
+
+        let cpu_time = match &self.target {
+            RenderTarget::Surface {
+                surface, config, ..
+            } => {
+                // Blocks on vsync, so the work is timed after it
+                let frame = surface.get_current_texture()?;
+                let start = Instant::now();
+                let view = frame
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+
+                let capture =
+                    self.capture
+                        .prepare(&self.device, config.width, config.height, config.format);
+                let mut encoder = self.draw(&view, capture.as_ref());
+                self.capture.record(&self.device, &mut encoder);
+                self.queue.submit(std::iter::once(encoder.finish()));
+                let cpu_time = start.elapsed();
+                frame.present();
+                cpu_time
+            }
+            RenderTarget::Offscreen(target) => {
+                let start = Instant::now();
+                let capture = self.capture.prepare(
+                    &self.device,
+                    target.width,
+                    target.height,
+                    OffscreenTarget::FORMAT,
+                );
+                let mut encoder = self.draw(&target.view, capture.as_ref());
+                self.capture.record(&self.device, &mut encoder);
+                self.queue.submit(std::iter::once(encoder.finish()));
+                start.elapsed()
+            }
+        };
+
+        self.capture.poll(&self.device);
+        self.update_render_scale(cpu_time.as_secs_f32());
+        Ok(())
+    }
+
+    /// Feeds the frame time to the dynamic resolution. Without GPU
+    /// timestamps that's `cpu_time`, from the frame start to the submit, so
+    /// waiting on vsync or the frame cap never counts as load.
+    fn update_render_scale(&mut self, cpu_time: f32) {
+        let frame_time = match &mut self.timer {
+            Some(timer) => {
+                timer.poll(&self.device);
+                timer.take()
+            }
+            None => Some(cpu_time),
+        };
+
+        if let Some(dynamic) = &mut self.dynamic_resolution
+            && let Some(frame_time) = frame_time
+            && let Some(scale) = dynamic.update(frame_time, self.render_scale)
+        {
+            self.set_render_scale(scale);
+        }
+    }
+
+    /// Renders a frame into the offscreen target and reads it back
+    pub fn render_image(&mut self) -> Result<RgbaImage> {
+        if !matches!(self.target, RenderTarget::Offscreen(_)) {
+            bail!("Capturing needs an offscreen renderer");
+        }
+
+        self.render()?;
+
+        let RenderTarget::Offscreen(target) = &self.target else {
+            unreachable!()
+        };
+        offscreen::read_texture(
+            &self.device,
+            &self.queue,
+            &target.texture,
+            target.width,
+            target.height,
+        )
+    }
+
+    pub fn save_screenshot(&mut self, path: &str) -> Result<()> {
+        log::info!("Saving screenshot to {path}");
+        self.render_image()?.save(path)?;
+        Ok(())
+    }
+
+    /// Encodes the scene and post-processing passes, the caller submits
+    fn draw(
+        &self,
+        view: &wgpu::TextureView,
+        capture: Option<&wgpu::TextureView>,
+    ) -> wgpu::CommandEncoder {
         self.uniforms.update(&self.queue);
+        self.pipelines.background_pipeline.update(&self.queue);
+        self.pipelines.bloom_pipeline.update(&self.queue);
+        self.pipelines.post_pipeline.update(&self.queue);
 
-        let frame = self.surface.get_current_texture()?;
-        let view = frame
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
+        if let Some(timer) = &self.timer {
+            timer.begin(&mut encoder);
+        }
+
+        self.pipelines.particle_pipeline.simulate(&mut encoder);
 
         {
+            // With MSAA the samples are resolved into the HDR texture here,
+            // before bloom and tone mapping read it
+            let (view, resolve_target) = self.pipelines.hdr_pipeline.color_attachment();
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: self.pipelines.hdr_pipeline.view(),
+                    view,
                     depth_slice: None,
-                    resolve_target: None,
+                    resolve_target,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
                             r: 0.1,
@@ -156,7 +589,7 @@ impl Renderer {
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: wgpu::LoadOp::Clear(texture::Texture::DEPTH_CLEAR),
                         store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
@@ -170,12 +603,17 @@ impl Renderer {
         }
 
         self.pipelines
-            .hdr_pipeline
-            .process(&mut encoder, &view, &self.uniforms.bind_group);
+            .bloom_pipeline
+            .process(&mut encoder, &self.pipelines.hdr_pipeline);
+        self.pipelines
+            .post_pipeline
+            .process(&mut encoder, view, capture);
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        frame.present();
-        Ok(())
+        if let Some(timer) = &self.timer {
+            timer.end(&mut encoder);
+        }
+
+        encoder
     }
 
     pub fn compute_normals(positions: &[Vec3], indices: &[u16]) -> Vec<Vec3> {
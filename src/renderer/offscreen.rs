@@ -0,0 +1,676 @@
+use anyhow::{Context, Result};
+use image::RgbaImage;
+
+/// Texture the renderer draws into instead of a window surface
+pub struct OffscreenTarget {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl OffscreenTarget {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            width,
+            height,
+        }
+    }
+}
+
+/// Padded row size for texture to buffer copies
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+    (width * 4).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
+}
+
+/// Creates a buffer that can receive a copy of a RGBA8 texture
+pub fn create_readback_buffer(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback buffer"),
+        size: (padded_bytes_per_row(width) * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    })
+}
+
+pub fn copy_texture_to_buffer(
+    encoder: &mut wgpu::CommandEncoder,
+    texture: &wgpu::Texture,
+    buffer: &wgpu::Buffer,
+    width: u32,
+    height: u32,
+) {
+    encoder.copy_texture_to_buffer(
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::TexelCopyBufferInfo {
+            buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row(width)),
+                rows_per_image: Some(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+/// Converts a mapped readback buffer into an image, dropping the row padding.
+/// BGRA data is swizzled, so surface formats can be captured too.
+pub fn buffer_to_image(data: &[u8], width: u32, height: u32, bgra: bool) -> Result<RgbaImage> {
+    let padded = padded_bytes_per_row(width) as usize;
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+
+    for row in data.chunks_exact(padded).take(height as usize) {
+        pixels.extend_from_slice(&row[..width as usize * 4]);
+    }
+
+    if bgra {
+        pixels.chunks_exact_mut(4).for_each(|p| p.swap(0, 2));
+    }
+
+    RgbaImage::from_raw(width, height, pixels).context("readback buffer is too small")
+}
+
+/// Copies a RGBA8 texture back to the CPU, blocking until the GPU is done
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    width: u32,
+    height: u32,
+) -> Result<RgbaImage> {
+    let buffer = create_readback_buffer(device, width, height);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback encoder"),
+    });
+    copy_texture_to_buffer(&mut encoder, texture, &buffer, width, height);
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::PollType::Wait)?;
+    receiver.recv()??;
+
+    let bgra = matches!(
+        texture.format(),
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+    );
+    let image = buffer_to_image(&slice.get_mapped_range(), width, height, bgra)?;
+    buffer.unmap();
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use glam::{Mat3, Mat4, Vec3};
+    use image::{DynamicImage, Rgba};
+    use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+    use super::*;
+    use crate::{
+        camera_effects::GameEvent,
+        particle_effects::{DEFAULT_PARTICLES, ParticleEffects},
+        renderer::{
+            Renderer,
+            pipeline::{
+                InstanceRaw,
+                background::{Background, CubemapSource, SkySettings, SkyboxSettings},
+                color::generate_sphere,
+                particle::EmitterSettings,
+                post::{PostEffect, ToneMapper},
+                texture::TexturedVertex,
+            },
+            resolution::DynamicResolution,
+            texture::Texture,
+        },
+    };
+
+    const SIZE: u32 = 128;
+
+    /// Channel difference that is still considered the same pixel, software
+    /// and hardware rasterizers don't agree on every bit
+    const TOLERANCE: u8 = 8;
+
+    fn renderer() -> Option<Renderer> {
+        match pollster::block_on(Renderer::new_offscreen(SIZE, SIZE, false)) {
+            Ok(renderer) => Some(renderer),
+            Err(e) => {
+                eprintln!("Skipping golden image test: {e}");
+                None
+            }
+        }
+    }
+
+    fn instance(translation: Vec3) -> InstanceRaw {
+        let model = Mat4::from_translation(translation);
+        InstanceRaw {
+            model: model.to_cols_array_2d(),
+            normal: Mat3::from_mat4(model).to_cols_array_2d(),
+        }
+    }
+
+    /// Sphere in front of the default camera, the subject of most goldens
+    fn add_test_sphere(renderer: &mut Renderer, color: [f32; 3]) {
+        let (vertices, indices) = generate_sphere(0.5, 16, 16, color);
+        renderer.pipelines.color_pipeline.add_mesh(
+            &renderer.device,
+            1,
+            &vertices,
+            &indices,
+            &[instance(Vec3::new(0.0, 1.0, 0.0))],
+        );
+    }
+
+    /// Compares against `tests/golden/<name>.png`. Goldens are only written
+    /// with `UPDATE_GOLDEN=1`, a missing one fails like a mismatch.
+    fn check_golden(name: &str, image: &RgbaImage) {
+        let path = format!("tests/golden/{name}.png");
+
+        if std::env::var("UPDATE_GOLDEN").is_ok_and(|value| value == "1") {
+            std::fs::create_dir_all("tests/golden").unwrap();
+            image.save(&path).unwrap();
+            return;
+        }
+
+        if !Path::new(&path).exists() {
+            std::fs::create_dir_all("tests/golden").unwrap();
+            let actual = format!("tests/golden/{name}.actual.png");
+            image.save(&actual).unwrap();
+            panic!("{path} is missing, see {actual} and rerun with UPDATE_GOLDEN=1 to accept it");
+        }
+
+        let golden = image::open(&path).unwrap().to_rgba8();
+        assert_eq!(golden.dimensions(), image.dimensions());
+
+        let mismatched = golden
+            .pixels()
+            .zip(image.pixels())
+            .filter(|(a, b)| a.0.iter().zip(b.0).any(|(a, b)| a.abs_diff(b) > TOLERANCE))
+            .count();
+
+        if mismatched > 0 {
+            let actual = format!("tests/golden/{name}.actual.png");
+            image.save(&actual).unwrap();
+            panic!("{mismatched} pixels differ from {path}, see {actual}");
+        }
+    }
+
+    #[test]
+    fn background_pipeline_golden() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        check_golden("background", &renderer.render_image().unwrap());
+    }
+
+    #[test]
+    fn color_pipeline_golden() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+
+        add_test_sphere(&mut renderer, [0.2, 0.8, 0.2]);
+
+        check_golden("color", &renderer.render_image().unwrap());
+    }
+
+    #[test]
+    fn texture_pipeline_golden() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+
+        let checker = RgbaImage::from_fn(8, 8, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([40, 60, 200, 255])
+            }
+        });
+        let texture = Texture::from_image(
+            &renderer.device,
+            &renderer.queue,
+            &DynamicImage::ImageRgba8(checker),
+            Some("checker"),
+        )
+        .unwrap();
+
+        let vertex = |x: f32, y: f32, u: f32, v: f32| TexturedVertex {
+            position: [x, y, 0.0],
+            tex_coords: [u, v],
+            normal: [0.0, 0.0, 1.0],
+        };
+        let vertices = [
+            vertex(-0.5, 0.5, 0.0, 1.0),
+            vertex(0.5, 0.5, 1.0, 1.0),
+            vertex(0.5, 1.5, 1.0, 0.0),
+            vertex(-0.5, 1.5, 0.0, 0.0),
+        ];
+
+        renderer.pipelines.texture_pipeline.add_mesh(
+            &renderer.device,
+            1,
+            &texture,
+            &vertices,
+            &[0, 1, 2, 0, 2, 3],
+            &[instance(Vec3::ZERO)],
+        );
+
+        check_golden("texture", &renderer.render_image().unwrap());
+    }
+
+    #[test]
+    fn hdr_pipeline_golden() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+
+        // Way over 1.0, so only tone mapping keeps it from clipping
+        add_test_sphere(&mut renderer, [8.0, 4.0, 1.0]);
+
+        check_golden("hdr", &renderer.render_image().unwrap());
+    }
+
+    #[test]
+    fn msaa_golden() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+
+        assert!(renderer.set_sample_count(3).is_err());
+        if !renderer.supported_sample_counts.contains(&4) {
+            eprintln!("Skipping MSAA golden, adapter has no 4x MSAA");
+            return;
+        }
+
+        // Added before the switch, meshes have to survive the pipeline rebuild
+        add_test_sphere(&mut renderer, [0.2, 0.8, 0.2]);
+        renderer.set_sample_count(4).unwrap();
+
+        check_golden("msaa", &renderer.render_image().unwrap());
+    }
+
+    #[test]
+    fn screen_to_world_ray_hits_the_pixel() {
+        let Some(renderer) = renderer() else {
+            return;
+        };
+        let camera = &renderer.uniforms.camera;
+        let size = PhysicalSize::new(SIZE, SIZE);
+
+        let center = camera.screen_to_world_ray(PhysicalPosition::new(64.0, 64.0), size);
+        assert!(center.origin.distance(camera.position) < 1e-4);
+        assert!(center.direction.distance(camera.calc_view_dir()) < 1e-4);
+
+        // A point along the ray lands back on the same pixel
+        let pixel = PhysicalPosition::new(16.0, 100.0);
+        let ray = camera.screen_to_world_ray(pixel, size);
+        let view_proj = camera.calc_proj_matrix() * camera.calc_view_matrix();
+        let ndc = view_proj.project_point3(ray.at(5.0));
+        let projected = (
+            (ndc.x + 1.0) / 2.0 * SIZE as f32,
+            (1.0 - ndc.y) / 2.0 * SIZE as f32,
+        );
+        assert!((projected.0 - 16.0).abs() < 1e-2, "{projected:?}");
+        assert!((projected.1 - 100.0).abs() < 1e-2, "{projected:?}");
+    }
+
+    #[test]
+    fn render_scale_golden() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+
+        add_test_sphere(&mut renderer, [0.2, 0.8, 0.2]);
+        renderer.set_render_scale(0.1);
+        assert_eq!(renderer.render_scale, 0.5);
+        assert_eq!(renderer.scene_size(), PhysicalSize::new(SIZE / 2, SIZE / 2));
+
+        let image = renderer.render_image().unwrap();
+        assert_eq!(image.dimensions(), (SIZE, SIZE));
+        check_golden("render_scale", &image);
+    }
+
+    #[test]
+    fn vsync_bound_frames_keep_the_scale() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        add_test_sphere(&mut renderer, [0.2, 0.8, 0.2]);
+
+        // An idle scene presented at the target rate, the rest of every
+        // frame interval is spent waiting like on vsync or the frame cap.
+        // The budget is generous, software rasterizers are slow.
+        let target_fps = 10.0;
+        renderer.dynamic_resolution = Some(DynamicResolution::new(target_fps));
+        // Measured on the CPU like without timestamp queries
+        renderer.timer = None;
+        for _ in 0..15 {
+            renderer.render().unwrap();
+            std::thread::sleep(Duration::from_secs_f32(1.0 / target_fps));
+        }
+
+        assert_eq!(renderer.render_scale, 1.0);
+    }
+
+    #[test]
+    fn tone_mapper_goldens() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+
+        add_test_sphere(&mut renderer, [8.0, 4.0, 1.0]);
+
+        for operator in [
+            ToneMapper::AgX,
+            ToneMapper::Reinhard,
+            ToneMapper::Uncharted2,
+        ] {
+            renderer
+                .pipelines
+                .post_pipeline
+                .settings
+                .tone_mapping
+                .operator = operator;
+            let name = format!("tone_map_{operator:?}").to_lowercase();
+            check_golden(&name, &renderer.render_image().unwrap());
+        }
+    }
+
+    #[test]
+    fn auto_exposure_adapts_to_bright_scene() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+
+        let brightness = |image: &RgbaImage| {
+            image.pixels().map(|p| p.0[1] as u64).sum::<u64>() / image.pixels().len() as u64
+        };
+        let manual = brightness(&renderer.render_image().unwrap());
+
+        // The background is well above middle grey, so exposure has to drop
+        let post = &mut renderer.pipelines.post_pipeline;
+        post.settings.tone_mapping.auto_exposure.enabled = true;
+        post.settings.tone_mapping.auto_exposure.adaptation_speed = 100.0;
+        post.advance(1.0);
+
+        let mut auto = manual;
+        for _ in 0..3 {
+            auto = brightness(&renderer.render_image().unwrap());
+        }
+
+        assert!(auto < manual, "auto exposure {auto} >= manual {manual}");
+    }
+
+    #[test]
+    fn post_stack_golden() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+
+        add_test_sphere(&mut renderer, [0.2, 0.8, 0.2]);
+
+        let post = &mut renderer.pipelines.post_pipeline;
+        for effect in PostEffect::ALL {
+            post.set_enabled(effect, true);
+        }
+        post.settings.tone_mapping.exposure = 1.5;
+        post.settings.chromatic_aberration = 0.05;
+
+        check_golden("post", &renderer.render_image().unwrap());
+    }
+
+    /// A small sphere in front of a larger one, added after it
+    fn add_depth_scene(renderer: &mut Renderer) {
+        let (near, near_indices) = generate_sphere(0.5, 16, 16, [1.0, 0.0, 0.0]);
+        let (far, far_indices) = generate_sphere(1.5, 16, 16, [0.0, 1.0, 0.0]);
+        for (id, vertices, indices, translation) in [
+            (1, &far, &far_indices, Vec3::new(0.0, 1.0, -3.0)),
+            (2, &near, &near_indices, Vec3::new(0.0, 1.0, 0.0)),
+        ] {
+            renderer.pipelines.color_pipeline.add_mesh(
+                &renderer.device,
+                id,
+                vertices,
+                indices,
+                &[instance(translation)],
+            );
+        }
+    }
+
+    #[test]
+    fn reverse_z_keeps_near_in_front() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        add_depth_scene(&mut renderer);
+
+        let image = renderer.render_image().unwrap();
+        let center = image.get_pixel(SIZE / 2, SIZE / 2).0;
+        assert!(center[0] > center[1], "center pixel {center:?} isn't red");
+    }
+
+    #[test]
+    fn orthographic_golden() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        add_depth_scene(&mut renderer);
+
+        let camera = &mut renderer.uniforms.camera;
+        camera.projection.orthographic = Some(6.0);
+        camera.update_uniform();
+
+        check_golden("orthographic", &renderer.render_image().unwrap());
+    }
+
+    #[test]
+    fn sky_golden() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        let background = Background::Sky(SkySettings {
+            stars: 0.3,
+            ..Default::default()
+        });
+        renderer
+            .pipelines
+            .background_pipeline
+            .set_background(&renderer.device, &renderer.queue, background)
+            .unwrap();
+
+        // Looking a little up, the stars fade in above the horizon
+        let camera = &mut renderer.uniforms.camera;
+        camera.pitch = 0.3;
+        camera.update_uniform();
+
+        check_golden("sky", &renderer.render_image().unwrap());
+    }
+
+    #[test]
+    fn particles_golden() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        add_depth_scene(&mut renderer);
+
+        let effects = ParticleEffects::load(DEFAULT_PARTICLES).unwrap();
+        for event in [
+            GameEvent::GlassBroken {
+                position: Vec3::new(-0.3, 1.3, 0.8),
+            },
+            GameEvent::BallImpact {
+                position: Vec3::new(0.3, 0.7, 0.8),
+                normal: Vec3::new(-1.0, 1.0, 0.0).normalize(),
+                speed: 15.0,
+            },
+        ] {
+            effects.handle(
+                event,
+                &mut renderer.pipelines.particle_pipeline,
+                &renderer.queue,
+            );
+        }
+        renderer.pipelines.particle_pipeline.advance(0.1);
+
+        check_golden("particles", &renderer.render_image().unwrap());
+    }
+
+    #[test]
+    fn skybox_turns_with_the_camera() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+
+        // Every face in its own color, +X red and -Z blue
+        let colors = [
+            [255, 0, 0],
+            [0, 255, 255],
+            [0, 255, 0],
+            [255, 0, 255],
+            [255, 255, 0],
+            [0, 0, 255],
+        ];
+        let dir = std::env::temp_dir().join("smashbit_skybox_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let faces = colors.map(|[r, g, b]| {
+            let path = dir.join(format!("{r}_{g}_{b}.png"));
+            RgbaImage::from_pixel(4, 4, Rgba([r, g, b, 255]))
+                .save(&path)
+                .unwrap();
+            path.to_str().unwrap().to_string()
+        });
+
+        let background = Background::Skybox(SkyboxSettings {
+            source: CubemapSource::Faces(faces),
+            intensity: 1.0,
+            horizon_fog: 0.0,
+        });
+        renderer
+            .pipelines
+            .background_pipeline
+            .set_background(&renderer.device, &renderer.queue, background)
+            .unwrap();
+
+        // The default camera looks down -Z
+        let image = renderer.render_image().unwrap();
+        let [r, g, b, _] = image.get_pixel(SIZE / 2, SIZE / 2).0;
+        assert!(
+            b > 200 && r < 50 && g < 50,
+            "expected blue, got {r} {g} {b}"
+        );
+
+        let camera = &mut renderer.uniforms.camera;
+        camera.yaw = 0.0;
+        camera.update_uniform();
+        let image = renderer.render_image().unwrap();
+        let [r, g, b, _] = image.get_pixel(SIZE / 2, SIZE / 2).0;
+        assert!(r > 200 && g < 50 && b < 50, "expected red, got {r} {g} {b}");
+    }
+
+    #[test]
+    fn particles_fall_with_the_gravity() {
+        // Where the burst ends up across the screen, from the pixels it changed
+        let drift = |gravity: Vec3| {
+            let mut renderer = renderer()?;
+            let background = renderer.render_image().unwrap();
+
+            let particles = &mut renderer.pipelines.particle_pipeline;
+            particles.gravity = gravity;
+            let emitter = EmitterSettings {
+                count: 8,
+                lifetime: (10.0, 10.0),
+                speed: (0.0, 0.0),
+                size: (0.1, 0.1),
+                drag: 0.0,
+                ..Default::default()
+            };
+            particles.emit(
+                &renderer.queue,
+                &emitter,
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::Y,
+                1.0,
+            );
+
+            let mut image = background.clone();
+            for _ in 0..3 {
+                renderer.pipelines.particle_pipeline.advance(0.1);
+                image = renderer.render_image().unwrap();
+            }
+
+            let changed: Vec<_> = image
+                .enumerate_pixels()
+                .filter(|(x, y, pixel)| background.get_pixel(*x, *y) != *pixel)
+                .map(|(x, _, _)| x as f32)
+                .collect();
+            assert!(!changed.is_empty(), "no particles drawn");
+            Some(changed.iter().sum::<f32>() / changed.len() as f32)
+        };
+
+        let (Some(right), Some(left)) = (drift(Vec3::X * 5.0), drift(Vec3::NEG_X * 5.0)) else {
+            return;
+        };
+        assert!(right > left + 5.0, "{right} {left}");
+    }
+
+    #[test]
+    fn fog_follows_the_view_elevation() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+
+        // Green at the screen center, the upper fog color has more of it
+        let mut green = |pitch: f32, yaw: f32| {
+            let camera = &mut renderer.uniforms.camera;
+            (camera.pitch, camera.yaw) = (pitch, yaw);
+            camera.update_uniform();
+            let image = renderer.render_image().unwrap();
+            image.get_pixel(SIZE / 2, SIZE / 2).0[1]
+        };
+
+        let up = green(1.2, 0.0);
+        let level = green(0.0, 0.0);
+        let down = green(-1.2, 0.0);
+        assert!(up > level && level > down, "{up} {level} {down}");
+        assert!(up - down > 50, "{up} {down}");
+        // Only the elevation matters
+        assert_eq!(green(1.2, 2.0), up);
+    }
+}
@@ -1,17 +1,309 @@
 // pipeline/background.rs
-use crate::renderer::texture;
-use wgpu::{BindGroupLayout, ShaderModuleDescriptor, ShaderSource, TextureFormat};
+use anyhow::Result;
+use serde::Deserialize;
+use wgpu::{BindGroupLayout, ShaderModuleDescriptor, ShaderSource, TextureFormat, util::DeviceExt};
 
+use crate::renderer::{
+    cubemap::{Cubemap, CubemapData},
+    texture,
+};
+
+/// What is drawn behind the level, part of the level manifest
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub enum Background {
+    /// The fog gradient, so distant geometry fades right into it
+    #[default]
+    Fog,
+    /// Procedural gradient with an optional star field
+    Sky(SkySettings),
+    Skybox(SkyboxSettings),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SkySettings {
+    /// Linear colors straight up, at the horizon and straight down
+    pub zenith: [f32; 3],
+    pub horizon: [f32; 3],
+    pub ground: [f32; 3],
+    /// Higher values keep the horizon color in a thinner band
+    pub falloff: f32,
+    /// Share of star field cells that hold a star, 0 turns stars off
+    pub stars: f32,
+    /// HDR brightness of the brightest stars, above 1 they bloom
+    pub star_brightness: f32,
+    /// How much fog color covers the horizon, so distant geometry blends in
+    pub horizon_fog: f32,
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        Self {
+            zenith: [0.05, 0.08, 0.25],
+            horizon: [1.0, 0.55, 0.4],
+            ground: [0.15, 0.1, 0.12],
+            falloff: 4.0,
+            stars: 0.0,
+            star_brightness: 4.0,
+            horizon_fog: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct SkyboxSettings {
+    pub source: CubemapSource,
+    /// Multiplies the cubemap colors
+    #[serde(default = "SkyboxSettings::default_intensity")]
+    pub intensity: f32,
+    /// See [SkySettings::horizon_fog]
+    #[serde(default)]
+    pub horizon_fog: f32,
+}
+
+impl SkyboxSettings {
+    fn default_intensity() -> f32 {
+        1.0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub enum CubemapSource {
+    /// KTX2 or DDS file with all six faces
+    File(String),
+    /// Images in [FACES](crate::renderer::cubemap::FACES) order
+    Faces([String; 6]),
+}
+
+impl CubemapSource {
+    pub fn load(&self) -> Result<CubemapData> {
+        match self {
+            Self::File(path) => CubemapData::load(path),
+            Self::Faces(paths) => CubemapData::load_faces(paths),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BackgroundUniform {
+    zenith: [f32; 4],
+    horizon: [f32; 4],
+    ground: [f32; 4],
+    /// 0 fog, 1 sky, 2 skybox
+    mode: u32,
+    falloff: f32,
+    stars: f32,
+    star_brightness: f32,
+    intensity: f32,
+    horizon_fog: f32,
+    _padding: [f32; 2],
+}
+
+impl From<&Background> for BackgroundUniform {
+    fn from(background: &Background) -> Self {
+        let sky = match background {
+            Background::Sky(sky) => *sky,
+            _ => SkySettings::default(),
+        };
+        let (mode, intensity, horizon_fog) = match background {
+            Background::Fog => (0, 1.0, 0.0),
+            Background::Sky(sky) => (1, 1.0, sky.horizon_fog),
+            Background::Skybox(skybox) => (2, skybox.intensity, skybox.horizon_fog),
+        };
+
+        Self {
+            zenith: rgba(sky.zenith),
+            horizon: rgba(sky.horizon),
+            ground: rgba(sky.ground),
+            mode,
+            falloff: sky.falloff.max(0.01),
+            stars: sky.stars.clamp(0.0, 1.0),
+            star_brightness: sky.star_brightness,
+            intensity,
+            horizon_fog: horizon_fog.clamp(0.0, 1.0),
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+fn rgba([r, g, b]: [f32; 3]) -> [f32; 4] {
+    [r, g, b, 1.0]
+}
+
+/// Draws the fog gradient, a procedural sky or a skybox behind the level.
+/// The view direction of every pixel comes from the inverse view projection,
+/// so the sky turns with the camera.
 pub struct BackgroundPipeline {
     pub pipeline: wgpu::RenderPipeline,
+    pub background: Background,
+    buffer: wgpu::Buffer,
+    bind_group_layout: BindGroupLayout,
+    sampler: wgpu::Sampler,
+    bind_group: wgpu::BindGroup,
 }
 
 impl BackgroundPipeline {
     pub fn new(
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         format: TextureFormat,
         base_bind_group_layout: &BindGroupLayout,
+        sample_count: u32,
     ) -> Self {
+        let background = Background::default();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Background buffer"),
+            contents: bytemuck::cast_slice(&[BackgroundUniform::from(&background)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Background bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Background sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let cubemap = Cubemap::placeholder(device, queue);
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &buffer, &cubemap, &sampler);
+
+        Self {
+            pipeline: Self::create_pipeline(
+                device,
+                format,
+                base_bind_group_layout,
+                &bind_group_layout,
+                sample_count,
+            ),
+            background,
+            buffer,
+            bind_group_layout,
+            sampler,
+            bind_group,
+        }
+    }
+
+    /// Recreates the pipeline, for example for a new MSAA sample count
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        format: TextureFormat,
+        base_bind_group_layout: &BindGroupLayout,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            format,
+            base_bind_group_layout,
+            &self.bind_group_layout,
+            sample_count,
+        );
+    }
+
+    /// Switches the background, loading the skybox if there is one. On an
+    /// error the current background is kept.
+    pub fn set_background(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        background: Background,
+    ) -> Result<()> {
+        let cubemap = match &background {
+            Background::Skybox(skybox) => Cubemap::new(device, queue, &skybox.source.load()?)?,
+            _ => Cubemap::placeholder(device, queue),
+        };
+
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.buffer,
+            &cubemap,
+            &self.sampler,
+        );
+        self.background = background;
+        Ok(())
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[BackgroundUniform::from(&self.background)]),
+        );
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        cubemap: &Cubemap,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Background bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&cubemap.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        format: TextureFormat,
+        base_bind_group_layout: &BindGroupLayout,
+        bind_group_layout: &BindGroupLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
         let shader = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Background shader"),
             source: ShaderSource::Wgsl(wesl::include_wesl!("background").into()),
@@ -19,11 +311,11 @@ impl BackgroundPipeline {
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Background pipeline layout"),
-            bind_group_layouts: &[base_bind_group_layout],
+            bind_group_layouts: &[base_bind_group_layout, bind_group_layout],
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Background pipeline"),
             layout: Some(&pipeline_layout),
             vertex: wgpu::VertexState {
@@ -58,16 +350,18 @@ impl BackgroundPipeline {
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
-            multisample: wgpu::MultisampleState::default(),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
             multiview: None,
             cache: None,
-        });
-
-        Self { pipeline }
+        })
     }
 
     pub fn begin_render_pass(&self, render_pass: &mut wgpu::RenderPass) {
         render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
         render_pass.draw(0..3, 0..1);
     }
 }
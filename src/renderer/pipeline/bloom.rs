@@ -0,0 +1,368 @@
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
+use crate::{level::Lerp, renderer::pipeline::hdr::HdrPipeline};
+
+/// Smallest mip the blur chain goes down to, in pixels
+const MIN_MIP_SIZE: u32 = 8;
+const MAX_MIPS: u32 = 6;
+
+/// Bloom parameters, part of the level manifest
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BloomSettings {
+    /// Brightness where pixels start to bloom
+    pub threshold: f32,
+    /// Width of the soft transition around the threshold
+    pub knee: f32,
+    /// How much of the blurred bloom is added back to the frame
+    pub intensity: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            knee: 0.2,
+            intensity: 0.3,
+        }
+    }
+}
+
+impl Lerp for BloomSettings {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            threshold: self.threshold.lerp(&other.threshold, t),
+            knee: self.knee.lerp(&other.knee, t),
+            intensity: self.intensity.lerp(&other.intensity, t),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    _padding: f32,
+}
+
+impl From<BloomSettings> for BloomUniform {
+    fn from(settings: BloomSettings) -> Self {
+        Self {
+            threshold: settings.threshold,
+            knee: settings.knee,
+            intensity: settings.intensity,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// Extracts the bright parts of the HDR texture, blurs them through a mip
+/// chain and adds them back, before post-processing tone maps
+pub struct BloomPipeline {
+    pub settings: BloomSettings,
+    pub enabled: bool,
+    buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    prefilter: wgpu::RenderPipeline,
+    downsample: wgpu::RenderPipeline,
+    upsample: wgpu::RenderPipeline,
+    composite: wgpu::RenderPipeline,
+    mips: Vec<wgpu::TextureView>,
+    /// Reads the HDR texture
+    hdr_bind_group: wgpu::BindGroup,
+    /// One per mip, each reads that mip
+    mip_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl BloomPipeline {
+    pub fn new(device: &wgpu::Device, hdr: &HdrPipeline) -> Self {
+        let settings = BloomSettings::default();
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Buffer"),
+            contents: bytemuck::cast_slice(&[BloomUniform::from(settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bloom::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Bloom::sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("bloom"),
+            source: wgpu::ShaderSource::Wgsl(wesl::include_wesl!("bloom").into()),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::OVER,
+        };
+
+        let create_pipeline = |entry_point: &str, blend: wgpu::BlendState| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(&format!("Bloom {entry_point} pipeline")),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: hdr.format(),
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        let prefilter = create_pipeline("fs_prefilter", wgpu::BlendState::REPLACE);
+        let downsample = create_pipeline("fs_downsample", wgpu::BlendState::REPLACE);
+        let upsample = create_pipeline("fs_upsample", additive);
+        let composite = create_pipeline("fs_composite", additive);
+
+        let (mips, hdr_bind_group, mip_bind_groups) =
+            Self::create_targets(device, &layout, &sampler, &buffer, hdr);
+
+        Self {
+            settings,
+            enabled: true,
+            buffer,
+            layout,
+            sampler,
+            prefilter,
+            downsample,
+            upsample,
+            composite,
+            mips,
+            hdr_bind_group,
+            mip_bind_groups,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        buffer: &wgpu::Buffer,
+        hdr: &HdrPipeline,
+    ) -> (
+        Vec<wgpu::TextureView>,
+        wgpu::BindGroup,
+        Vec<wgpu::BindGroup>,
+    ) {
+        // The chain starts at half resolution
+        let width = (hdr.width / 2).max(1);
+        let height = (hdr.height / 2).max(1);
+        let mip_count = (width.min(height) / MIN_MIP_SIZE)
+            .max(1)
+            .ilog2()
+            .clamp(1, MAX_MIPS);
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Bloom::texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mip_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: hdr.format(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        let mips: Vec<_> = (0..mip_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Bloom::mip"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let create_bind_group = |view: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Bloom::bind_group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+
+        let hdr_bind_group = create_bind_group(hdr.view());
+        let mip_bind_groups = mips.iter().map(create_bind_group).collect();
+
+        (mips, hdr_bind_group, mip_bind_groups)
+    }
+
+    /// Recreates the mip chain for a resized HDR texture
+    pub fn resize(&mut self, device: &wgpu::Device, hdr: &HdrPipeline) {
+        (self.mips, self.hdr_bind_group, self.mip_bind_groups) =
+            Self::create_targets(device, &self.layout, &self.sampler, &self.buffer, hdr);
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[BloomUniform::from(self.settings)]),
+        );
+    }
+
+    fn pass(
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Bloom::pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    /// Adds bloom to the HDR texture in place
+    pub fn process(&self, encoder: &mut wgpu::CommandEncoder, hdr: &HdrPipeline) {
+        if !self.enabled || self.settings.intensity <= 0.0 {
+            return;
+        }
+
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT);
+
+        Self::pass(
+            encoder,
+            &self.prefilter,
+            &self.hdr_bind_group,
+            &self.mips[0],
+            clear,
+        );
+
+        for mip in 1..self.mips.len() {
+            Self::pass(
+                encoder,
+                &self.downsample,
+                &self.mip_bind_groups[mip - 1],
+                &self.mips[mip],
+                clear,
+            );
+        }
+
+        // Each smaller mip is blurred into the next larger one on top of
+        // what the downsample left there
+        for mip in (1..self.mips.len()).rev() {
+            Self::pass(
+                encoder,
+                &self.upsample,
+                &self.mip_bind_groups[mip],
+                &self.mips[mip - 1],
+                wgpu::LoadOp::Load,
+            );
+        }
+
+        Self::pass(
+            encoder,
+            &self.composite,
+            &self.mip_bind_groups[0],
+            hdr.view(),
+            wgpu::LoadOp::Load,
+        );
+    }
+}
@@ -49,7 +49,31 @@ impl ColorPipeline {
         device: &wgpu::Device,
         format: wgpu::TextureFormat,
         base_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
     ) -> Self {
+        Self {
+            pipeline: Self::create_pipeline(device, format, base_bind_group_layout, sample_count),
+            meshes: LiteMap::new(),
+        }
+    }
+
+    /// Recreates the pipeline, for example for a new MSAA sample count
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        base_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(device, format, base_bind_group_layout, sample_count);
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        base_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
         let shader = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Color shader"),
             source: ShaderSource::Wgsl(wesl::include_wesl!("main").into()),
@@ -61,7 +85,7 @@ impl ColorPipeline {
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Color Pipeline"),
             layout: Some(&pipeline_layout),
             vertex: wgpu::VertexState {
@@ -92,23 +116,18 @@ impl ColorPipeline {
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: texture::Texture::DEPTH_FORMAT,
                 depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual,
+                depth_compare: texture::Texture::DEPTH_COMPARE,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
             cache: None,
-        });
-
-        Self {
-            pipeline,
-            meshes: LiteMap::new(),
-        }
+        })
     }
 
     pub fn add_mesh(
@@ -220,10 +239,10 @@ pub fn generate_sphere(
     }
 
     for i in 0..stacks {
-        let mut k1 = i * (sectors + 1);
-        let mut k2 = k1 + sectors + 1;
+        for j in 0..sectors {
+            let k1 = i * (sectors + 1) + j;
+            let k2 = k1 + sectors + 1;
 
-        for _ in 0..sectors {
             if i != 0 {
                 indices.push(k1);
                 indices.push(k2);
@@ -234,8 +253,6 @@ pub fn generate_sphere(
                 indices.push(k2);
                 indices.push(k2 + 1);
             }
-            k1 += 1;
-            k2 += 1;
         }
     }
 
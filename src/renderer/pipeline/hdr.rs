@@ -2,29 +2,45 @@ use winit::dpi::PhysicalSize;
 
 use crate::renderer::texture;
 
+/// Floating point target the scene is rendered into. Bloom and the
+/// [PostPipeline](super::post::PostPipeline) take it from there.
 pub struct HdrPipeline {
-    pub pipeline: wgpu::RenderPipeline,
-    pub bind_group: wgpu::BindGroup,
     pub texture: texture::Texture,
+    /// Drawn into instead of `texture` with MSAA, resolved into it at the
+    /// end of the scene pass
+    pub multisampled: Option<wgpu::TextureView>,
     pub width: u32,
     pub height: u32,
     pub format: wgpu::TextureFormat,
-    pub layout: wgpu::BindGroupLayout,
+    pub sample_count: u32,
 }
 
 impl HdrPipeline {
-    pub fn new(
-        device: &wgpu::Device,
-        size: &PhysicalSize<u32>,
-        base_bind_group_layout: &wgpu::BindGroupLayout,
-    ) -> Self {
+    // We could use `Rgba32Float`, but that requires some extra
+    // features to be enabled for rendering.
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    pub fn new(device: &wgpu::Device, size: &PhysicalSize<u32>, sample_count: u32) -> Self {
         let (width, height) = (size.width, size.height);
+        let format = Self::FORMAT;
 
-        // We could use `Rgba32Float`, but that requires some extra
-        // features to be enabled for rendering.
-        let format = wgpu::TextureFormat::Rgba16Float;
+        Self {
+            texture: Self::create_texture(device, width, height, format),
+            multisampled: Self::create_multisampled(device, width, height, format, sample_count),
+            width,
+            height,
+            format,
+            sample_count,
+        }
+    }
 
-        let texture = texture::Texture::create_texture(
+    fn create_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> texture::Texture {
+        texture::Texture::create_texture(
             device,
             width,
             height,
@@ -32,170 +48,67 @@ impl HdrPipeline {
             wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
             wgpu::FilterMode::Nearest,
             Some("Hdr::texture"),
-        );
-
-        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Hdr::layout"),
-            entries: &[
-                // This is the HDR texture
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
-        });
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Hdr::bind_group"),
-            layout: &layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                },
-            ],
-        });
-
-        // We'll cover the shader next
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("hdr"),
-            source: wgpu::ShaderSource::Wgsl(wesl::include_wesl!("hdr").into()),
-        });
+        )
+    }
 
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &[base_bind_group_layout, &layout],
-            push_constant_ranges: &[],
-        });
+    fn create_multisampled(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Option<wgpu::TextureView> {
+        if sample_count <= 1 {
+            return None;
+        }
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Hdr pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[],
-                compilation_options: Default::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: format.add_srgb_suffix(),
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: Default::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Hdr::multisampled"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
             },
-            multiview: None,
-            cache: None,
-        });
-
-        Self {
-            pipeline,
-            bind_group,
-            layout,
-            texture,
-            width,
-            height,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
             format,
-        }
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
     }
 
     /// Resize the HDR texture
     pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
-        self.texture = texture::Texture::create_texture(
-            device,
-            width,
-            height,
-            wgpu::TextureFormat::Rgba16Float,
-            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
-            wgpu::FilterMode::Nearest,
-            Some("Hdr::texture"),
-        );
-        self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Hdr::bind_group"),
-            layout: &self.layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&self.texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&self.texture.sampler),
-                },
-            ],
-        });
+        self.texture = Self::create_texture(device, width, height, self.format);
+        self.multisampled =
+            Self::create_multisampled(device, width, height, self.format, self.sample_count);
         self.width = width;
         self.height = height;
     }
 
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.sample_count = sample_count;
+        self.multisampled =
+            Self::create_multisampled(device, self.width, self.height, self.format, sample_count);
+    }
+
     /// Exposes the HDR texture
     pub fn view(&self) -> &wgpu::TextureView {
         &self.texture.view
     }
 
+    /// The view the scene pass draws into and its resolve target, if any
+    pub fn color_attachment(&self) -> (&wgpu::TextureView, Option<&wgpu::TextureView>) {
+        match &self.multisampled {
+            Some(multisampled) => (multisampled, Some(&self.texture.view)),
+            None => (&self.texture.view, None),
+        }
+    }
+
     /// The format of the HDR texture
     pub fn format(&self) -> wgpu::TextureFormat {
         self.format
     }
-
-    /// This renders the internal HDR texture to the [TextureView]
-    /// supplied as parameter.
-    pub fn process(
-        &self,
-        encoder: &mut wgpu::CommandEncoder,
-        view: &wgpu::TextureView,
-        base_bind_group: &wgpu::BindGroup,
-    ) {
-        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Hdr::process"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view,
-                depth_slice: None,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Load,
-                    store: wgpu::StoreOp::Store,
-                },
-            })],
-            ..Default::default()
-        });
-
-        pass.set_pipeline(&self.pipeline);
-        pass.set_bind_group(0, base_bind_group, &[]);
-        pass.set_bind_group(1, &self.bind_group, &[]);
-        pass.draw(0..3, 0..1);
-    }
 }
@@ -0,0 +1,206 @@
+use wgpu::util::DeviceExt;
+
+use crate::renderer::pipeline::{hdr::HdrPipeline, post::AutoExposure};
+
+const BINS: u64 = 256;
+const HISTOGRAM_WORKGROUP: u32 = 16;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LuminanceUniform {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    dt: f32,
+    adaptation_speed: f32,
+    pixel_count: u32,
+    _padding: [u32; 3],
+}
+
+/// Builds a luminance histogram of the HDR texture on the GPU and adapts
+/// the average luminance towards it, for automatic exposure
+pub struct LuminancePipeline {
+    /// Adapted average luminance as a single `f32`
+    pub average: wgpu::Buffer,
+    histogram: wgpu::Buffer,
+    buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    width: u32,
+    height: u32,
+}
+
+impl LuminancePipeline {
+    pub fn new(device: &wgpu::Device, hdr: &HdrPipeline) -> Self {
+        let histogram = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance::histogram"),
+            size: BINS * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        // Starts at middle grey, so the first frames aren't blown out
+        let average = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Luminance::average"),
+            contents: bytemuck::cast_slice(&[0.18f32]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance Buffer"),
+            size: size_of::<LuminanceUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Luminance::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                storage_entry(1),
+                storage_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("luminance"),
+            source: wgpu::ShaderSource::Wgsl(wesl::include_wesl!("luminance").into()),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Luminance pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(&format!("Luminance {entry_point} pipeline")),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+
+        let bind_group =
+            Self::create_bind_group(device, &layout, hdr, &histogram, &average, &buffer);
+
+        Self {
+            histogram_pipeline: create_pipeline("cs_histogram"),
+            average_pipeline: create_pipeline("cs_average"),
+            average,
+            histogram,
+            buffer,
+            layout,
+            bind_group,
+            width: hdr.width,
+            height: hdr.height,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        hdr: &HdrPipeline,
+        histogram: &wgpu::Buffer,
+        average: &wgpu::Buffer,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Luminance::bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(hdr.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: histogram.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: average.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, hdr: &HdrPipeline) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            hdr,
+            &self.histogram,
+            &self.average,
+            &self.buffer,
+        );
+        (self.width, self.height) = (hdr.width, hdr.height);
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, settings: &AutoExposure, dt: f32) {
+        let uniform = LuminanceUniform {
+            min_log_luminance: settings.min_log_luminance,
+            log_luminance_range: settings.max_log_luminance - settings.min_log_luminance,
+            dt,
+            adaptation_speed: settings.adaptation_speed,
+            pixel_count: self.width * self.height,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    pub fn process(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Luminance::process"),
+            timestamp_writes: None,
+        });
+
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_pipeline(&self.histogram_pipeline);
+        pass.dispatch_workgroups(
+            self.width.div_ceil(HISTOGRAM_WORKGROUP),
+            self.height.div_ceil(HISTOGRAM_WORKGROUP),
+            1,
+        );
+        pass.set_pipeline(&self.average_pipeline);
+        pass.dispatch_workgroups(1, 1, 1);
+    }
+}
@@ -1,13 +1,17 @@
 use winit::dpi::PhysicalSize;
 
 use crate::renderer::pipeline::{
-    background::BackgroundPipeline, color::ColorPipeline, hdr::HdrPipeline,
-    texture::TexturePipeline,
+    background::BackgroundPipeline, bloom::BloomPipeline, color::ColorPipeline, hdr::HdrPipeline,
+    particle::ParticlePipeline, post::PostPipeline, texture::TexturePipeline,
 };
 
 pub mod background;
+pub mod bloom;
 pub mod color;
 pub mod hdr;
+pub mod luminance;
+pub mod particle;
+pub mod post;
 pub mod texture;
 
 #[repr(C)]
@@ -66,40 +70,89 @@ impl InstanceRaw {
 
 pub struct Pipelines {
     pub hdr_pipeline: HdrPipeline,
+    pub bloom_pipeline: BloomPipeline,
+    pub post_pipeline: PostPipeline,
     pub background_pipeline: BackgroundPipeline,
     pub color_pipeline: ColorPipeline,
     pub texture_pipeline: TexturePipeline,
+    pub particle_pipeline: ParticlePipeline,
 }
 
 impl Pipelines {
     pub fn new(
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         size: &PhysicalSize<u32>,
         base_bind_group_layout: &wgpu::BindGroupLayout,
+        output_format: wgpu::TextureFormat,
+        sample_count: u32,
     ) -> Self {
-        let hdr_pipeline = HdrPipeline::new(device, size, base_bind_group_layout);
+        let hdr_pipeline = HdrPipeline::new(device, size, sample_count);
         Self {
             background_pipeline: BackgroundPipeline::new(
                 device,
+                queue,
                 hdr_pipeline.format(),
                 base_bind_group_layout,
+                sample_count,
             ),
             color_pipeline: ColorPipeline::new(
                 device,
                 hdr_pipeline.format(),
                 base_bind_group_layout,
+                sample_count,
             ),
             texture_pipeline: TexturePipeline::new(
                 device,
                 hdr_pipeline.format(),
                 base_bind_group_layout,
+                sample_count,
             ),
+            particle_pipeline: ParticlePipeline::new(
+                device,
+                hdr_pipeline.format(),
+                base_bind_group_layout,
+                sample_count,
+            ),
+            bloom_pipeline: BloomPipeline::new(device, &hdr_pipeline),
+            post_pipeline: PostPipeline::new(device, queue, &hdr_pipeline, output_format),
             hdr_pipeline,
         }
     }
 
-    pub fn resize(&mut self, device: &wgpu::Device, size: &PhysicalSize<u32>) {
+    /// Rebuilds the scene pipelines and the multisampled HDR target.
+    /// Meshes are kept.
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        base_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) {
+        self.hdr_pipeline.set_sample_count(device, sample_count);
+
+        let format = self.hdr_pipeline.format();
+        self.background_pipeline
+            .rebuild(device, format, base_bind_group_layout, sample_count);
+        self.color_pipeline
+            .rebuild(device, format, base_bind_group_layout, sample_count);
+        self.texture_pipeline
+            .rebuild(device, format, base_bind_group_layout, sample_count);
+        self.particle_pipeline
+            .rebuild(device, format, base_bind_group_layout, sample_count);
+    }
+
+    /// `size` is the resolution the scene is rendered at, `output` the one
+    /// post-processing writes
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        size: &PhysicalSize<u32>,
+        output: &PhysicalSize<u32>,
+    ) {
         self.hdr_pipeline.resize(device, size.width, size.height);
+        self.bloom_pipeline.resize(device, &self.hdr_pipeline);
+        self.post_pipeline
+            .resize(device, &self.hdr_pipeline, output);
     }
 
     pub fn begin_render_pass(&self, pass: &mut wgpu::RenderPass) {
@@ -107,5 +160,8 @@ impl Pipelines {
 
         self.color_pipeline.begin_render_pass(pass);
         self.texture_pipeline.begin_render_pass(pass);
+
+        // After the level, they don't write depth
+        self.particle_pipeline.begin_render_pass(pass);
     }
 }
@@ -0,0 +1,558 @@
+use std::f32::consts::TAU;
+
+use glam::Vec3;
+use serde::Deserialize;
+use wgpu::{BindGroupLayout, ShaderModuleDescriptor, ShaderSource, TextureFormat};
+
+use crate::{physics::PhysicsSettings, renderer::texture};
+
+/// Particles alive at once, new ones replace the oldest
+pub const MAX_PARTICLES: usize = 8192;
+const SIMULATION_WORKGROUP: u32 = 64;
+/// Longest step the simulation takes, so a hitch doesn't fling particles
+const MAX_STEP: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum ParticleShape {
+    /// Round sprite facing the camera, for dust and glows
+    #[default]
+    Billboard,
+    /// Stretched along the velocity, for sparks
+    Streak,
+    /// Tumbling triangle in world space, for glass
+    Shard,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum ParticleBlend {
+    /// Adds light, order doesn't matter
+    #[default]
+    Additive,
+    /// Covers what is behind, drawn unsorted after the additive particles
+    Alpha,
+}
+
+/// One burst of particles, emitter definitions live in data files
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct EmitterSettings {
+    /// Particles per burst at full strength
+    pub count: u32,
+    pub shape: ParticleShape,
+    pub blend: ParticleBlend,
+    /// Seconds, every particle picks a value in the range
+    pub lifetime: (f32, f32),
+    /// Units per second
+    pub speed: (f32, f32),
+    /// Degrees away from the emit direction, 180 sprays every way
+    pub spread: f32,
+    /// Size at the start and at the end of the lifetime
+    pub size: (f32, f32),
+    /// Linear HDR colors over the lifetime, alpha included
+    pub color_start: [f32; 4],
+    pub color_end: [f32; 4],
+    /// Multiplies the gravity, negative values float up
+    pub gravity: f32,
+    /// Velocity lost per second, as a rate
+    pub drag: f32,
+    /// Most radians per second a particle turns
+    pub spin: f32,
+    /// Streak length per unit per second of speed
+    pub stretch: f32,
+    /// Particles start up to this far from the emitter
+    pub radius: f32,
+}
+
+impl Default for EmitterSettings {
+    fn default() -> Self {
+        Self {
+            count: 16,
+            shape: ParticleShape::default(),
+            blend: ParticleBlend::default(),
+            lifetime: (0.5, 1.0),
+            speed: (1.0, 3.0),
+            spread: 45.0,
+            size: (0.1, 0.0),
+            color_start: [1.0; 4],
+            color_end: [1.0, 1.0, 1.0, 0.0],
+            gravity: 1.0,
+            drag: 0.5,
+            spin: 0.0,
+            stretch: 0.02,
+            radius: 0.0,
+        }
+    }
+}
+
+impl EmitterSettings {
+    /// Particles of one burst from `position` towards `direction`.
+    /// `strength` from 0 to 1 scales the count.
+    pub fn spawn(
+        &self,
+        rng: &mut Rng,
+        position: Vec3,
+        direction: Vec3,
+        strength: f32,
+    ) -> Vec<ParticleRaw> {
+        let count = (self.count as f32 * strength.clamp(0.0, 1.0)).round() as usize;
+        let direction = direction.try_normalize().unwrap_or(Vec3::Y);
+        let spread = self.spread.clamp(0.0, 180.0).to_radians();
+
+        (0..count.min(MAX_PARTICLES))
+            .map(|_| {
+                let position = position + rng.unit_vector() * self.radius * rng.next_f32();
+                let velocity = rng.cone(direction, spread) * rng.range(self.speed);
+                let spin_axis = rng.unit_vector();
+
+                ParticleRaw {
+                    position: position.extend(0.0).to_array(),
+                    velocity: velocity
+                        .extend(rng.range(self.lifetime).max(f32::EPSILON))
+                        .to_array(),
+                    color_start: self.color_start,
+                    color_end: self.color_end,
+                    params: [self.size.0, self.size.1, self.drag, self.gravity],
+                    spin: spin_axis
+                        .extend(rng.range((-self.spin, self.spin)))
+                        .to_array(),
+                    shape: self.shape as u32,
+                    blend: self.blend as u32,
+                    rotation: rng.next_f32() * TAU,
+                    stretch: self.stretch,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Small deterministic generator for spawning, so replays and golden
+/// images get the same particles
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        Self(seed.max(1))
+    }
+
+    /// From 0 to 1
+    pub fn next_f32(&mut self) -> f32 {
+        // xorshift32
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+
+    pub fn range(&mut self, (min, max): (f32, f32)) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    pub fn unit_vector(&mut self) -> Vec3 {
+        self.cone(Vec3::Y, std::f32::consts::PI)
+    }
+
+    /// Uniformly distributed direction at most `angle` radians from `axis`
+    pub fn cone(&mut self, axis: Vec3, angle: f32) -> Vec3 {
+        let cos_theta = 1.0 - self.next_f32() * (1.0 - angle.cos());
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let (sin_phi, cos_phi) = (self.next_f32() * TAU).sin_cos();
+
+        let (tangent, bitangent) = axis.any_orthonormal_pair();
+        axis * cos_theta + (tangent * cos_phi + bitangent * sin_phi) * sin_theta
+    }
+}
+
+/// Particle state on the GPU, simulated by `particle_simulation.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ParticleRaw {
+    /// xyz and the age in seconds
+    pub position: [f32; 4],
+    /// xyz and the lifetime in seconds, particles die when their age reaches it
+    pub velocity: [f32; 4],
+    pub color_start: [f32; 4],
+    pub color_end: [f32; 4],
+    /// Start size, end size, drag and gravity
+    pub params: [f32; 4],
+    /// Normalized spin axis and radians per second
+    pub spin: [f32; 4],
+    pub shape: u32,
+    pub blend: u32,
+    pub rotation: f32,
+    pub stretch: f32,
+}
+
+impl ParticleRaw {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 8] = wgpu::vertex_attr_array![
+        0 => Float32x4,
+        1 => Float32x4,
+        2 => Float32x4,
+        3 => Float32x4,
+        4 => Float32x4,
+        5 => Float32x4,
+        6 => Uint32x2,
+        7 => Float32x2,
+    ];
+
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<ParticleRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SimulationUniform {
+    gravity: [f32; 3],
+    dt: f32,
+    count: u32,
+    _padding: [u32; 3],
+}
+
+/// Simulates particles in a compute pass and draws them into the HDR
+/// target. Bursts are written into a ring buffer, the oldest particles
+/// make room for new ones.
+pub struct ParticlePipeline {
+    particles: wgpu::Buffer,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    simulation: wgpu::ComputePipeline,
+    additive: wgpu::RenderPipeline,
+    alpha: wgpu::RenderPipeline,
+    /// Ring slot the next particle goes in
+    next: usize,
+    /// Simulated time not yet stepped
+    pending: f32,
+    /// Step the next compute pass takes
+    step: f32,
+    /// Pulls on particles scaled by their gravity, kept in line with the
+    /// level physics
+    pub gravity: Vec3,
+    pub rng: Rng,
+}
+
+impl ParticlePipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        format: TextureFormat,
+        base_bind_group_layout: &BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let particles = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particles"),
+            size: (MAX_PARTICLES * size_of::<ParticleRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle simulation buffer"),
+            size: size_of::<SimulationUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Particle simulation layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Particle simulation bind group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: particles.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Particle simulation shader"),
+            source: ShaderSource::Wgsl(wesl::include_wesl!("particle_simulation").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle simulation pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let simulation = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Particle simulation pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let (additive, alpha) =
+            Self::create_pipelines(device, format, base_bind_group_layout, sample_count);
+
+        Self {
+            particles,
+            buffer,
+            bind_group,
+            simulation,
+            additive,
+            alpha,
+            next: 0,
+            pending: 0.0,
+            step: 0.0,
+            gravity: PhysicsSettings::default().gravity,
+            rng: Rng::new(0x5EED),
+        }
+    }
+
+    /// Recreates the pipelines, for example for a new MSAA sample count
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        format: TextureFormat,
+        base_bind_group_layout: &BindGroupLayout,
+        sample_count: u32,
+    ) {
+        (self.additive, self.alpha) =
+            Self::create_pipelines(device, format, base_bind_group_layout, sample_count);
+    }
+
+    /// Spawns a burst, see [EmitterSettings::spawn]
+    pub fn emit(
+        &mut self,
+        queue: &wgpu::Queue,
+        emitter: &EmitterSettings,
+        position: Vec3,
+        direction: Vec3,
+        strength: f32,
+    ) {
+        let particles = emitter.spawn(&mut self.rng, position, direction, strength);
+
+        let mut written = 0;
+        while written < particles.len() {
+            let count = (particles.len() - written).min(MAX_PARTICLES - self.next);
+            queue.write_buffer(
+                &self.particles,
+                (self.next * size_of::<ParticleRaw>()) as wgpu::BufferAddress,
+                bytemuck::cast_slice(&particles[written..written + count]),
+            );
+            written += count;
+            self.next = (self.next + count) % MAX_PARTICLES;
+        }
+    }
+
+    /// Moves the simulation forward by `dt` seconds on the next frame
+    pub fn advance(&mut self, dt: f32) {
+        self.pending += dt;
+    }
+
+    /// Prepares the step of the next [Self::simulate]
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        self.step = self.pending.min(MAX_STEP);
+        self.pending = 0.0;
+
+        if self.step > 0.0 {
+            queue.write_buffer(
+                &self.buffer,
+                0,
+                bytemuck::cast_slice(&[SimulationUniform {
+                    gravity: self.gravity.to_array(),
+                    dt: self.step,
+                    count: MAX_PARTICLES as u32,
+                    _padding: [0; 3],
+                }]),
+            );
+        }
+    }
+
+    pub fn simulate(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.step <= 0.0 {
+            return;
+        }
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Particle simulation pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.simulation);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.dispatch_workgroups((MAX_PARTICLES as u32).div_ceil(SIMULATION_WORKGROUP), 1, 1);
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        format: TextureFormat,
+        base_bind_group_layout: &BindGroupLayout,
+        sample_count: u32,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Particle shader"),
+            source: ShaderSource::Wgsl(wesl::include_wesl!("particle").into()),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle pipeline layout"),
+            bind_group_layouts: &[base_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipeline = |blend: ParticleBlend| {
+            let (label, vertex, fragment, blend) = match blend {
+                ParticleBlend::Additive => (
+                    "Additive particle pipeline",
+                    "vs_additive",
+                    "fs_additive",
+                    wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Zero,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                    },
+                ),
+                ParticleBlend::Alpha => (
+                    "Alpha particle pipeline",
+                    "vs_alpha",
+                    "fs_alpha",
+                    wgpu::BlendState::ALPHA_BLENDING,
+                ),
+            };
+
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some(vertex),
+                    buffers: &[ParticleRaw::desc()],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(fragment),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    // Shards tumble, both sides show
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                // Hidden behind the level, but they don't hide each other
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: texture::Texture::DEPTH_COMPARE,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        (
+            create_pipeline(ParticleBlend::Additive),
+            create_pipeline(ParticleBlend::Alpha),
+        )
+    }
+
+    /// Draws every slot, dead particles and those of the other blend mode
+    /// collapse in the vertex shader
+    pub fn begin_render_pass(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.set_vertex_buffer(0, self.particles.slice(..));
+        for pipeline in [&self.additive, &self.alpha] {
+            render_pass.set_pipeline(pipeline);
+            render_pass.draw(0..6, 0..MAX_PARTICLES as u32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawns_within_the_cone() {
+        let emitter = EmitterSettings {
+            count: 200,
+            spread: 30.0,
+            speed: (2.0, 4.0),
+            lifetime: (1.0, 2.0),
+            ..Default::default()
+        };
+        let mut rng = Rng::new(1);
+        let particles = emitter.spawn(&mut rng, Vec3::ONE, Vec3::X * 3.0, 1.0);
+        assert_eq!(particles.len(), 200);
+
+        for particle in &particles {
+            let velocity = Vec3::from_slice(&particle.velocity[..3]);
+            let speed = velocity.length();
+            assert!((2.0..=4.0).contains(&speed), "speed {speed}");
+            assert!(velocity.angle_between(Vec3::X) <= 30f32.to_radians() + 1e-4);
+            assert!((1.0..=2.0).contains(&particle.velocity[3]));
+            assert_eq!(Vec3::from_slice(&particle.position[..3]), Vec3::ONE);
+            assert_eq!(particle.position[3], 0.0);
+        }
+
+        // Weaker bursts have fewer particles
+        assert_eq!(emitter.spawn(&mut rng, Vec3::ZERO, Vec3::X, 0.25).len(), 50);
+    }
+
+    #[test]
+    fn rng_is_deterministic() {
+        let (mut a, mut b) = (Rng::new(7), Rng::new(7));
+        for _ in 0..100 {
+            let value = a.next_f32();
+            assert_eq!(value, b.next_f32());
+            assert!((0.0..1.0).contains(&value));
+        }
+    }
+}
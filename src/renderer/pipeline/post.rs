@@ -0,0 +1,709 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Result, bail};
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+use winit::dpi::PhysicalSize;
+
+use crate::renderer::{
+    pipeline::{hdr::HdrPipeline, luminance::LuminancePipeline},
+    texture,
+};
+
+/// Edge length of the identity color grading LUT
+const IDENTITY_LUT_SIZE: u32 = 16;
+
+/// A fullscreen pass of the post-processing stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PostEffect {
+    Exposure,
+    ChromaticAberration,
+    ToneMap,
+    ColorGrading,
+    Vignette,
+    FilmGrain,
+}
+
+impl PostEffect {
+    pub const ALL: [PostEffect; 6] = [
+        PostEffect::Exposure,
+        PostEffect::ChromaticAberration,
+        PostEffect::ToneMap,
+        PostEffect::ColorGrading,
+        PostEffect::Vignette,
+        PostEffect::FilmGrain,
+    ];
+
+    fn entry_point(self) -> &'static str {
+        match self {
+            PostEffect::Exposure => "fs_exposure",
+            PostEffect::ChromaticAberration => "fs_chromatic_aberration",
+            PostEffect::ToneMap => "fs_tone_map",
+            PostEffect::ColorGrading => "fs_color_grading",
+            PostEffect::Vignette => "fs_vignette",
+            PostEffect::FilmGrain => "fs_film_grain",
+        }
+    }
+}
+
+/// Curve that maps HDR values into the displayable range
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToneMapper {
+    /// Clamps to `[0, 1]`
+    None,
+    #[default]
+    Aces,
+    /// Keeps hue in bright saturated colors, where ACES skews them
+    AgX,
+    Reinhard,
+    /// John Hable's filmic curve
+    Uncharted2,
+}
+
+/// Exposure that follows the average scene luminance, measured with a
+/// histogram of the HDR texture
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoExposure {
+    pub enabled: bool,
+    /// Luminance the scene average is mapped to, middle grey by default
+    pub key: f32,
+    pub min_exposure: f32,
+    pub max_exposure: f32,
+    /// How quickly exposure follows changes, per second
+    pub adaptation_speed: f32,
+    /// log2 luminance range the histogram covers
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            key: 0.18,
+            min_exposure: 0.1,
+            max_exposure: 10.0,
+            adaptation_speed: 1.5,
+            min_log_luminance: -8.0,
+            max_log_luminance: 4.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToneMapping {
+    pub operator: ToneMapper,
+    /// Manual exposure, multiplied with the automatic one when enabled
+    pub exposure: f32,
+    pub auto_exposure: AutoExposure,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: ToneMapper::default(),
+            exposure: 1.0,
+            auto_exposure: AutoExposure::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VignetteSettings {
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance from the center where darkening starts, 1 is a corner
+    pub radius: f32,
+    pub smoothness: f32,
+}
+
+impl Default for VignetteSettings {
+    fn default() -> Self {
+        Self {
+            color: [0.0; 3],
+            intensity: 0.5,
+            radius: 0.5,
+            smoothness: 0.5,
+        }
+    }
+}
+
+/// Parameters of every effect, disabled effects ignore theirs
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostSettings {
+    pub tone_mapping: ToneMapping,
+    /// Channel offset at the screen edges, in UV units
+    pub chromatic_aberration: f32,
+    pub vignette: VignetteSettings,
+    /// Blend between the original and the graded color
+    pub grading_strength: f32,
+    pub grain_intensity: f32,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            tone_mapping: ToneMapping::default(),
+            chromatic_aberration: 0.01,
+            vignette: VignetteSettings::default(),
+            grading_strength: 1.0,
+            grain_intensity: 0.04,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    vignette: [f32; 4],
+    exposure: f32,
+    tone_mapper: u32,
+    chromatic_aberration: f32,
+    grading_strength: f32,
+    grain_intensity: f32,
+    time: f32,
+    vignette_radius: f32,
+    vignette_smoothness: f32,
+    auto_exposure: u32,
+    exposure_key: f32,
+    min_exposure: f32,
+    max_exposure: f32,
+}
+
+/// Screen tint that fades out, used as hit feedback
+#[derive(Clone, Copy, Debug)]
+struct Flash {
+    color: [f32; 3],
+    duration: f32,
+    remaining: f32,
+}
+
+/// Ordered chain of fullscreen effects between the HDR texture and the
+/// output. Effects ping-pong between two HDR targets, a final blit copies
+/// the result to the output view, upscaling it when the scene is rendered
+/// at a different resolution.
+pub struct PostPipeline {
+    /// Order the effects run in, effects can appear more than once
+    pub effects: Vec<PostEffect>,
+    pub settings: PostSettings,
+    enabled: HashSet<PostEffect>,
+    flash: Option<Flash>,
+    time: f32,
+    /// Length of the last frame, for exposure adaptation
+    dt: f32,
+
+    buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    lut: texture::Texture,
+    luminance: LuminancePipeline,
+    pipelines: HashMap<PostEffect, wgpu::RenderPipeline>,
+    blit: wgpu::RenderPipeline,
+    upscale: wgpu::RenderPipeline,
+    /// The HDR texture and the output differ in size
+    scaled: bool,
+
+    targets: [wgpu::TextureView; 2],
+    /// Reads the HDR texture
+    hdr_bind_group: wgpu::BindGroup,
+    /// One per ping-pong target
+    target_bind_groups: [wgpu::BindGroup; 2],
+}
+
+impl PostPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        hdr: &HdrPipeline,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
+        let settings = PostSettings::default();
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Buffer"),
+            contents: bytemuck::cast_slice(&[Self::uniform(&settings, None, 0.0)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Post::layout"),
+            entries: &[
+                texture_entry(0, wgpu::TextureViewDimension::D2),
+                sampler_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(3, wgpu::TextureViewDimension::D3),
+                sampler_entry(4),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let sampler = linear_sampler(device, "Post::sampler");
+        let lut = Self::create_lut(device, queue, &identity_lut(IDENTITY_LUT_SIZE))
+            .expect("identity LUT is valid");
+        let luminance = LuminancePipeline::new(device, hdr);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("post"),
+            source: wgpu::ShaderSource::Wgsl(wesl::include_wesl!("post").into()),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipeline = |entry_point: &str, format| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(&format!("Post {entry_point} pipeline")),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        let pipelines = PostEffect::ALL
+            .into_iter()
+            .map(|effect| (effect, create_pipeline(effect.entry_point(), hdr.format())))
+            .collect();
+        let blit = create_pipeline("fs_blit", output_format);
+        let upscale = create_pipeline("fs_upscale", output_format);
+
+        let (targets, hdr_bind_group, target_bind_groups) =
+            Self::create_targets(device, &layout, &sampler, &buffer, &lut, &luminance, hdr);
+
+        Self {
+            effects: PostEffect::ALL.to_vec(),
+            settings,
+            enabled: HashSet::from([PostEffect::Exposure, PostEffect::ToneMap]),
+            flash: None,
+            time: 0.0,
+            dt: 0.0,
+            buffer,
+            layout,
+            sampler,
+            lut,
+            luminance,
+            pipelines,
+            blit,
+            upscale,
+            scaled: false,
+            targets,
+            hdr_bind_group,
+            target_bind_groups,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        buffer: &wgpu::Buffer,
+        lut: &texture::Texture,
+        luminance: &LuminancePipeline,
+        hdr: &HdrPipeline,
+    ) -> (
+        [wgpu::TextureView; 2],
+        wgpu::BindGroup,
+        [wgpu::BindGroup; 2],
+    ) {
+        let targets = [0, 1].map(|_| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Post::target"),
+                    size: wgpu::Extent3d {
+                        width: hdr.width,
+                        height: hdr.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: hdr.format(),
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        });
+
+        let create_bind_group = |view: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Post::bind_group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&lut.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::Sampler(&lut.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: luminance.average.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+
+        let hdr_bind_group = create_bind_group(hdr.view());
+        let target_bind_groups = [
+            create_bind_group(&targets[0]),
+            create_bind_group(&targets[1]),
+        ];
+
+        (targets, hdr_bind_group, target_bind_groups)
+    }
+
+    fn rebuild_bind_groups(&mut self, device: &wgpu::Device, hdr: &HdrPipeline) {
+        (self.targets, self.hdr_bind_group, self.target_bind_groups) = Self::create_targets(
+            device,
+            &self.layout,
+            &self.sampler,
+            &self.buffer,
+            &self.lut,
+            &self.luminance,
+            hdr,
+        );
+    }
+
+    /// Recreates the ping-pong targets for a resized HDR texture, `output`
+    /// is the size of the views passed to [PostPipeline::process]
+    pub fn resize(&mut self, device: &wgpu::Device, hdr: &HdrPipeline, output: &PhysicalSize<u32>) {
+        self.luminance.resize(device, hdr);
+        self.rebuild_bind_groups(device, hdr);
+        self.scaled = (hdr.width, hdr.height) != (output.width, output.height);
+    }
+
+    fn create_lut(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        strip: &RgbaImage,
+    ) -> Result<texture::Texture> {
+        let size = strip.height();
+        if size < 2 || strip.width() != size * size {
+            bail!(
+                "LUT must be a strip of {size} {size}x{size} slices, got {}x{}",
+                strip.width(),
+                strip.height()
+            );
+        }
+
+        // The strip has blue slices side by side, red along x and green along y
+        let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.extend_from_slice(&strip.get_pixel(b * size + r, g).0);
+                }
+            }
+        }
+
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Post::lut"),
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: size,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            &data,
+        );
+
+        Ok(texture::Texture {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            sampler: linear_sampler(device, "Post::lut_sampler"),
+            texture,
+        })
+    }
+
+    /// Replaces the color grading LUT. `strip` holds `n` slices of `n`x`n`
+    /// side by side, the common layout LUT tools export.
+    pub fn set_lut(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        hdr: &HdrPipeline,
+        strip: &RgbaImage,
+    ) -> Result<()> {
+        self.lut = Self::create_lut(device, queue, strip)?;
+        self.rebuild_bind_groups(device, hdr);
+        Ok(())
+    }
+
+    pub fn load_lut(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        hdr: &HdrPipeline,
+        path: &str,
+    ) -> Result<()> {
+        log::info!("Loading color grading LUT {path}");
+        self.set_lut(device, queue, hdr, &image::open(path)?.to_rgba8())
+    }
+
+    pub fn is_enabled(&self, effect: PostEffect) -> bool {
+        self.enabled.contains(&effect)
+    }
+
+    pub fn set_enabled(&mut self, effect: PostEffect, enabled: bool) {
+        if enabled {
+            self.enabled.insert(effect);
+        } else {
+            self.enabled.remove(&effect);
+        }
+    }
+
+    /// Tints the screen edges with `color`, fading out over `duration`
+    /// seconds. Works with the vignette disabled too.
+    pub fn flash(&mut self, color: [f32; 3], duration: f32) {
+        self.flash = Some(Flash {
+            color,
+            duration,
+            remaining: duration,
+        });
+    }
+
+    /// Advances animated effects, film grain and flashes
+    pub fn advance(&mut self, dt: f32) {
+        self.time += dt;
+        self.dt = dt;
+
+        if let Some(flash) = &mut self.flash {
+            flash.remaining -= dt;
+            if flash.remaining <= 0.0 {
+                self.flash = None;
+            }
+        }
+    }
+
+    fn uniform(settings: &PostSettings, flash: Option<&Flash>, time: f32) -> PostUniform {
+        let auto_exposure = settings.tone_mapping.auto_exposure;
+        let mut vignette = settings.vignette;
+        if let Some(flash) = flash {
+            let strength = (flash.remaining / flash.duration).clamp(0.0, 1.0);
+            vignette.color = std::array::from_fn(|i| {
+                vignette.color[i] + (flash.color[i] - vignette.color[i]) * strength
+            });
+            vignette.intensity = vignette.intensity.max(strength);
+        }
+
+        PostUniform {
+            vignette: [
+                vignette.color[0],
+                vignette.color[1],
+                vignette.color[2],
+                vignette.intensity,
+            ],
+            exposure: settings.tone_mapping.exposure,
+            tone_mapper: settings.tone_mapping.operator as u32,
+            chromatic_aberration: settings.chromatic_aberration,
+            grading_strength: settings.grading_strength,
+            grain_intensity: settings.grain_intensity,
+            time: wrap_time(time),
+            vignette_radius: vignette.radius,
+            vignette_smoothness: vignette.smoothness,
+            auto_exposure: auto_exposure.enabled as u32,
+            exposure_key: auto_exposure.key,
+            min_exposure: auto_exposure.min_exposure,
+            max_exposure: auto_exposure.max_exposure,
+        }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[Self::uniform(
+                &self.settings,
+                self.flash.as_ref(),
+                self.time,
+            )]),
+        );
+
+        if self.settings.tone_mapping.auto_exposure.enabled {
+            self.luminance
+                .update(queue, &self.settings.tone_mapping.auto_exposure, self.dt);
+        }
+    }
+
+    fn pass(
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post::pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    /// Runs the enabled effects on the HDR texture and writes the result to
+    /// the [TextureView](wgpu::TextureView) supplied as parameter, and to
+    /// `capture` as well when given
+    pub fn process(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        capture: Option<&wgpu::TextureView>,
+    ) {
+        if self.settings.tone_mapping.auto_exposure.enabled {
+            self.luminance.process(encoder);
+        }
+
+        let mut source = &self.hdr_bind_group;
+        let mut target = 0;
+
+        for effect in &self.effects {
+            let flashing = *effect == PostEffect::Vignette && self.flash.is_some();
+            if !self.is_enabled(*effect) && !flashing {
+                continue;
+            }
+
+            Self::pass(
+                encoder,
+                &self.pipelines[effect],
+                source,
+                &self.targets[target],
+            );
+            source = &self.target_bind_groups[target];
+            target = 1 - target;
+        }
+
+        let blit = if self.scaled {
+            &self.upscale
+        } else {
+            &self.blit
+        };
+        Self::pass(encoder, blit, source, view);
+        if let Some(capture) = capture {
+            Self::pass(encoder, blit, source, capture);
+        }
+    }
+}
+
+fn linear_sampler(device: &wgpu::Device, label: &str) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some(label),
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    })
+}
+
+/// Keeps the grain time small, so `f32` precision doesn't freeze the noise
+fn wrap_time(time: f32) -> f32 {
+    time % 1000.0
+}
+
+/// LUT strip that leaves colors unchanged
+pub fn identity_lut(size: u32) -> RgbaImage {
+    let scale = |v: u32| (v * 255 / (size - 1)) as u8;
+    RgbaImage::from_fn(size * size, size, |x, y| {
+        image::Rgba([scale(x % size), scale(y), scale(x / size), 255])
+    })
+}
@@ -50,6 +50,7 @@ impl TexturePipeline {
         device: &wgpu::Device,
         format: wgpu::TextureFormat,
         base_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
     ) -> Self {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[
@@ -73,6 +74,43 @@ impl TexturePipeline {
             label: Some("Texture bind group layout"),
         });
 
+        Self {
+            pipeline: Self::create_pipeline(
+                device,
+                format,
+                base_bind_group_layout,
+                &bind_group_layout,
+                sample_count,
+            ),
+            bind_group_layout,
+            meshes: LiteMap::new(),
+        }
+    }
+
+    /// Recreates the pipeline, for example for a new MSAA sample count
+    pub fn rebuild(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        base_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            format,
+            base_bind_group_layout,
+            &self.bind_group_layout,
+            sample_count,
+        );
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        base_bind_group_layout: &wgpu::BindGroupLayout,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
         let shader = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Texture shader"),
             source: ShaderSource::Wgsl(wesl::include_wesl!("texture").into()),
@@ -80,11 +118,11 @@ impl TexturePipeline {
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Texture Pipeline Layout"),
-            bind_group_layouts: &[base_bind_group_layout, &bind_group_layout],
+            bind_group_layouts: &[base_bind_group_layout, bind_group_layout],
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Texture Pipeline"),
             layout: Some(&pipeline_layout),
             vertex: wgpu::VertexState {
@@ -115,24 +153,18 @@ impl TexturePipeline {
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: texture::Texture::DEPTH_FORMAT,
                 depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual,
+                depth_compare: texture::Texture::DEPTH_COMPARE,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
             cache: None,
-        });
-
-        Self {
-            pipeline,
-            bind_group_layout,
-            meshes: LiteMap::new(),
-        }
+        })
     }
 
     pub fn add_mesh(
@@ -0,0 +1,132 @@
+use std::ops::RangeInclusive;
+
+/// Render scales the scene can be drawn at, relative to the output size
+pub const RENDER_SCALE_RANGE: RangeInclusive<f32> = 0.5..=2.0;
+
+/// Steps the scale moves in, so small frame time jitter doesn't
+/// reallocate the scene targets
+const SCALE_STEP: f32 = 0.05;
+
+/// Adjusts the render scale from measured frame times to hold a frame rate
+pub struct DynamicResolution {
+    pub target_fps: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// Smoothed frame time in seconds
+    average: Option<f32>,
+    /// Frames left before the scale may change again. Frame times from
+    /// before a change are still in flight when it happens.
+    cooldown: u32,
+}
+
+impl DynamicResolution {
+    const COOLDOWN: u32 = 30;
+    const SMOOTHING: f32 = 0.1;
+    /// Scale down above this share of the frame budget...
+    const UPPER: f32 = 0.95;
+    /// ...and up below this one, the gap keeps it from oscillating
+    const LOWER: f32 = 0.75;
+
+    pub fn new(target_fps: f32) -> Self {
+        Self {
+            target_fps,
+            min_scale: *RENDER_SCALE_RANGE.start(),
+            max_scale: 1.0,
+            average: None,
+            cooldown: 0,
+        }
+    }
+
+    /// Feeds the time of one frame in seconds, returns a new scale when
+    /// `scale` should change
+    pub fn update(&mut self, frame_time: f32, scale: f32) -> Option<f32> {
+        let average = match self.average {
+            Some(average) => average + (frame_time - average) * Self::SMOOTHING,
+            None => frame_time,
+        };
+        self.average = Some(average);
+
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+            return None;
+        }
+
+        let load = average * self.target_fps;
+        if (Self::LOWER..=Self::UPPER).contains(&load) {
+            return None;
+        }
+
+        // Frame time is roughly proportional to the pixel count, which
+        // grows with the square of the scale. Aim for the middle of the band.
+        let ideal = scale * ((Self::LOWER + Self::UPPER) / 2.0 / load).sqrt();
+        let new_scale = ((ideal / SCALE_STEP).round() * SCALE_STEP)
+            .clamp(scale - 4.0 * SCALE_STEP, scale + 4.0 * SCALE_STEP)
+            .clamp(self.min_scale, self.max_scale);
+
+        if (new_scale - scale).abs() < SCALE_STEP / 2.0 {
+            return None;
+        }
+
+        self.cooldown = Self::COOLDOWN;
+        Some(new_scale)
+    }
+}
+
+/// Size of the scene targets for an output of `width` x `height`, limited
+/// to `max` texels per side
+pub fn scaled_size(width: u32, height: u32, scale: f32, max: u32) -> (u32, u32) {
+    let scale = |v: u32| ((v as f32 * scale).round() as u32).clamp(1, max);
+    (scale(width), scale(height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settle(
+        resolution: &mut DynamicResolution,
+        mut scale: f32,
+        cost: impl Fn(f32) -> f32,
+    ) -> f32 {
+        for _ in 0..1000 {
+            if let Some(new_scale) = resolution.update(cost(scale), scale) {
+                scale = new_scale;
+            }
+        }
+        scale
+    }
+
+    #[test]
+    fn lowers_scale_when_over_budget() {
+        let mut resolution = DynamicResolution::new(60.0);
+        // 25 ms at full scale
+        let scale = settle(&mut resolution, 1.0, |scale| 0.025 * scale * scale);
+
+        let load = 0.025 * scale * scale * 60.0;
+        assert!(scale < 1.0);
+        assert!(load <= DynamicResolution::UPPER, "load {load} at {scale}");
+    }
+
+    #[test]
+    fn raises_scale_with_headroom() {
+        let mut resolution = DynamicResolution::new(60.0);
+        let scale = settle(&mut resolution, 0.5, |scale| 0.008 * scale * scale);
+
+        assert_eq!(scale, resolution.max_scale);
+    }
+
+    #[test]
+    fn stays_in_range() {
+        let mut resolution = DynamicResolution::new(60.0);
+        let scale = settle(&mut resolution, 1.0, |_| 1.0);
+
+        assert_eq!(scale, resolution.min_scale);
+    }
+
+    #[test]
+    fn scaled_size_is_never_empty() {
+        assert_eq!(scaled_size(1, 1, 0.5, 8192), (1, 1));
+        assert_eq!(scaled_size(1280, 720, 0.5, 8192), (640, 360));
+        assert_eq!(scaled_size(4096, 2048, 4.0, 8192), (8192, 8192));
+    }
+}
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+
+/// Present mode used with vsync off, the other one is the fallback
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnsyncedPresentMode {
+    /// Newest frame replaces the queued one, no tearing
+    #[default]
+    Mailbox,
+    /// Frames are shown right away, lowest latency but tears
+    Immediate,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RendererSettings {
+    pub vsync: bool,
+    pub unsynced_present_mode: UnsyncedPresentMode,
+    /// Frames per second the game loop sleeps down to, `None` runs as fast
+    /// as presenting allows
+    pub frame_cap: Option<f32>,
+    /// Frames the CPU may queue ahead of the GPU
+    pub max_frame_latency: u32,
+    /// MSAA samples, lowered to what the adapter supports
+    pub msaa: u32,
+    /// See [RENDER_SCALE_RANGE](super::resolution::RENDER_SCALE_RANGE)
+    pub render_scale: f32,
+    /// Frame rate the render scale is adjusted for, `None` keeps it fixed
+    pub dynamic_resolution: Option<f32>,
+    /// Vertical field of view in degrees
+    pub fov: f32,
+}
+
+impl Default for RendererSettings {
+    fn default() -> Self {
+        Self {
+            vsync: true,
+            unsynced_present_mode: UnsyncedPresentMode::default(),
+            frame_cap: None,
+            max_frame_latency: 2,
+            msaa: 4,
+            render_scale: 1.0,
+            dynamic_resolution: None,
+            fov: 45.0,
+        }
+    }
+}
+
+impl RendererSettings {
+    /// Best match for the settings among the `supported` present modes
+    pub fn present_mode(&self, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+        use wgpu::PresentMode::{Fifo, Immediate, Mailbox};
+
+        let preference: &[wgpu::PresentMode] = match (self.vsync, self.unsynced_present_mode) {
+            (true, _) => &[Fifo],
+            (false, UnsyncedPresentMode::Mailbox) => &[Mailbox, Immediate, Fifo],
+            (false, UnsyncedPresentMode::Immediate) => &[Immediate, Mailbox, Fifo],
+        };
+
+        preference
+            .iter()
+            .copied()
+            .find(|mode| supported.contains(mode))
+            // Fifo is always supported
+            .unwrap_or(Fifo)
+    }
+
+    /// Time between frames under the frame cap
+    pub fn frame_time(&self) -> Option<f32> {
+        self.frame_cap.filter(|&fps| fps > 0.0).map(|fps| 1.0 / fps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::PresentMode::{Fifo, Immediate, Mailbox};
+
+    #[test]
+    fn vsync_uses_fifo() {
+        let settings = RendererSettings::default();
+        assert_eq!(settings.present_mode(&[Immediate, Mailbox, Fifo]), Fifo);
+    }
+
+    #[test]
+    fn unsynced_falls_back() {
+        let mut settings = RendererSettings {
+            vsync: false,
+            ..Default::default()
+        };
+        assert_eq!(settings.present_mode(&[Fifo, Immediate]), Immediate);
+
+        settings.unsynced_present_mode = UnsyncedPresentMode::Immediate;
+        assert_eq!(
+            settings.present_mode(&[Fifo, Mailbox, Immediate]),
+            Immediate
+        );
+        assert_eq!(settings.present_mode(&[Fifo]), Fifo);
+    }
+}
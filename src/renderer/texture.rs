@@ -9,6 +9,11 @@ pub struct Texture {
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    /// Depth is reversed, see [Projection](super::uniform::camera::Projection),
+    /// so nearer fragments have greater depth
+    pub const DEPTH_COMPARE: wgpu::CompareFunction = wgpu::CompareFunction::GreaterEqual;
+    /// Depth of the far plane
+    pub const DEPTH_CLEAR: f32 = 0.0;
 
     pub fn from_bytes(
         device: &wgpu::Device,
@@ -133,6 +138,7 @@ impl Texture {
         device: &wgpu::Device,
         width: u32,
         height: u32,
+        sample_count: u32,
         label: &str,
     ) -> Self {
         log::info!("Creating depth texture");
@@ -143,14 +149,23 @@ impl Texture {
             depth_or_array_layers: 1,
         };
 
+        // A multisampled depth texture can't be sampled with a comparison
+        // sampler anyway, and GL wants it to be a renderbuffer like the
+        // multisampled color attachment
+        let usage = if sample_count > 1 {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+        } else {
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+        };
+
         let desc = wgpu::TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage,
             view_formats: &[],
         };
         let texture = device.create_texture(&desc);
@@ -163,7 +178,7 @@ impl Texture {
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
-            compare: Some(wgpu::CompareFunction::LessEqual),
+            compare: Some(Self::DEPTH_COMPARE),
             lod_min_clamp: 0.0,
             lod_max_clamp: 100.0,
             ..Default::default()
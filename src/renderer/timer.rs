@@ -0,0 +1,109 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// Measures how long the GPU spends on a frame with timestamp queries.
+/// Results arrive a few frames late, the render loop never waits for them.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    mapping: bool,
+    ready: Arc<AtomicBool>,
+    last: Option<f32>,
+}
+
+impl GpuTimer {
+    pub const FEATURES: wgpu::Features =
+        wgpu::Features::TIMESTAMP_QUERY.union(wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS);
+    const SIZE: wgpu::BufferAddress = 2 * wgpu::QUERY_SIZE as wgpu::BufferAddress;
+
+    /// `None` when the device can't write timestamps between passes
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(Self::FEATURES) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GpuTimer::query_set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let resolve = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuTimer::resolve"),
+            size: Self::SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GpuTimer::readback"),
+            size: Self::SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve,
+            readback,
+            period: queue.get_timestamp_period(),
+            mapping: false,
+            ready: Arc::new(AtomicBool::new(false)),
+            last: None,
+        })
+    }
+
+    /// Must come before the first pass of the frame
+    pub fn begin(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.write_timestamp(&self.query_set, 0);
+    }
+
+    /// Must come after the last pass of the frame
+    pub fn end(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.write_timestamp(&self.query_set, 1);
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve, 0);
+
+        // The readback buffer is still busy with an older frame
+        if !self.mapping {
+            encoder.copy_buffer_to_buffer(&self.resolve, 0, &self.readback, 0, Self::SIZE);
+        }
+    }
+
+    /// Must be called after the encoder passed to [GpuTimer::end] was
+    /// submitted. Picks up the result once the GPU is done with it.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        if !self.mapping {
+            let ready = self.ready.clone();
+            self.readback
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| match result {
+                    Ok(()) => ready.store(true, Ordering::Release),
+                    Err(e) => log::error!("Failed to map timer buffer: {e}"),
+                });
+            self.mapping = true;
+        }
+
+        if let Err(e) = device.poll(wgpu::PollType::Poll) {
+            log::error!("Failed to poll device: {e}");
+        }
+        if !self.ready.swap(false, Ordering::Acquire) {
+            return;
+        }
+
+        let timestamps: [u64; 2] =
+            bytemuck::pod_read_unaligned(&self.readback.slice(..).get_mapped_range());
+        self.readback.unmap();
+        self.mapping = false;
+
+        let ticks = timestamps[1].saturating_sub(timestamps[0]);
+        self.last = Some(ticks as f32 * self.period / 1e9);
+    }
+
+    /// GPU time of the newest finished frame in seconds, once per result
+    pub fn take(&mut self) -> Option<f32> {
+        self.last.take()
+    }
+}
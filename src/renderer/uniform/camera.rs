@@ -1,5 +1,104 @@
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Quat, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
 use wgpu::util::DeviceExt;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+/// Where the camera is and where it looks, without any GPU state
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraState {
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl CameraState {
+    pub fn view_dir(&self) -> Vec3 {
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+
+        Vec3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
+    }
+}
+
+/// How the camera projects the scene. Depth is reversed, 1 at the near
+/// plane and 0 at the far one, which keeps its precision where it's needed.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Projection {
+    /// Vertical field of view in degrees
+    pub fovy: f32,
+    pub znear: f32,
+    /// `None` puts the far plane at infinity
+    pub zfar: Option<f32>,
+    /// Orthographic instead of perspective, with this many units from the
+    /// bottom to the top of the view. For looking over whole levels.
+    pub orthographic: Option<f32>,
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Self {
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: None,
+            orthographic: None,
+        }
+    }
+}
+
+impl Projection {
+    /// Far plane of orthographic views without one
+    const ORTHOGRAPHIC_FAR: f32 = 1000.0;
+
+    /// Reverse-Z projection matrix, `fovy_offset` is added to the field of view
+    pub fn matrix(&self, aspect: f32, fovy_offset: f32) -> Mat4 {
+        if let Some(height) = self.orthographic {
+            let (half_width, half_height) = (height * aspect * 0.5, height * 0.5);
+            let zfar = self.zfar.unwrap_or(Self::ORTHOGRAPHIC_FAR);
+            // Near and far swapped for reverse-Z
+            return Mat4::orthographic_rh(
+                -half_width,
+                half_width,
+                -half_height,
+                half_height,
+                zfar,
+                self.znear,
+            );
+        }
+
+        let fovy = (self.fovy + fovy_offset).clamp(1.0, 170.0).to_radians();
+        match self.zfar {
+            Some(zfar) => Mat4::perspective_rh(fovy, aspect, zfar, self.znear),
+            None => Mat4::perspective_infinite_reverse_rh(fovy, aspect, self.znear),
+        }
+    }
+}
+
+/// Added on top of the controlled pose by camera effects. Only the view
+/// sees it, [Camera::state] stays where the controller put the camera.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct CameraOffset {
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Radians around the view direction
+    pub roll: f32,
+    /// Degrees added to the vertical field of view
+    pub fovy: f32,
+}
+
+/// Half line in world space, `direction` is normalized
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+impl Ray {
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+}
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -29,11 +128,10 @@ pub struct Camera {
     pub position: Vec3,
     pub yaw: f32,
     pub pitch: f32,
+    pub offset: CameraOffset,
+    pub projection: Projection,
 
     aspect: f32,
-    fovy: f32,
-    znear: f32,
-    zfar: f32,
     uniform: CameraUniform,
     pub buffer: wgpu::Buffer,
 
@@ -48,9 +146,6 @@ impl Camera {
         let yaw = -90.0f32.to_radians();
         let pitch = 0.0;
         let aspect = width as f32 / height as f32;
-        let fovy = 45.0;
-        let znear = 0.1;
-        let zfar = 100.0;
 
         let uniform = CameraUniform::new();
 
@@ -64,10 +159,9 @@ impl Camera {
             position,
             yaw,
             pitch,
+            offset: CameraOffset::default(),
+            projection: Projection::default(),
             aspect,
-            fovy,
-            znear,
-            zfar,
             uniform,
             buffer,
             bind_layout_entry: wgpu::BindGroupLayoutEntry {
@@ -85,19 +179,67 @@ impl Camera {
         camera
     }
 
+    pub fn state(&self) -> CameraState {
+        CameraState {
+            position: self.position,
+            yaw: self.yaw,
+            pitch: self.pitch,
+        }
+    }
+
     pub fn calc_view_dir(&self) -> Vec3 {
-        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
-        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        self.state().view_dir()
+    }
 
-        Vec3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
+    /// Pose the view is rendered from, with the offset applied
+    pub fn view_state(&self) -> CameraState {
+        CameraState {
+            position: self.position + self.offset.position,
+            yaw: self.yaw + self.offset.yaw,
+            pitch: self.pitch + self.offset.pitch,
+        }
     }
 
     pub fn calc_view_matrix(&self) -> Mat4 {
-        Mat4::look_to_rh(self.position, self.calc_view_dir(), Vec3::Y)
+        let view = self.view_state();
+        let dir = view.view_dir();
+        let up = Quat::from_axis_angle(dir, self.offset.roll) * Vec3::Y;
+        Mat4::look_to_rh(view.position, dir, up)
     }
 
     pub fn calc_proj_matrix(&self) -> Mat4 {
-        Mat4::perspective_rh_gl(self.fovy.to_radians(), self.aspect, self.znear, self.zfar)
+        self.projection.matrix(self.aspect, self.offset.fovy)
+    }
+
+    /// Ray from the camera through a pixel of a `size` sized screen, for
+    /// picking with the cursor or touches
+    pub fn screen_to_world_ray(
+        &self,
+        position: PhysicalPosition<f64>,
+        size: PhysicalSize<u32>,
+    ) -> Ray {
+        let inv_view_proj = Mat4::from_cols_array_2d(&self.uniform.inv_view_proj);
+        let size = Vec2::new(size.width.max(1) as f32, size.height.max(1) as f32);
+        // Pixels go down from the top left, NDC up from the center
+        let ndc = Vec2::new(position.x as f32, position.y as f32) / size * 2.0 - 1.0;
+        let ndc = Vec2::new(ndc.x, -ndc.y);
+
+        // Two depths that are inside the frustum whatever the depth range
+        let a = inv_view_proj.project_point3(ndc.extend(0.25));
+        let b = inv_view_proj.project_point3(ndc.extend(0.75));
+
+        let view = self.view_state();
+        let forward = view.view_dir();
+        let mut direction = (b - a).normalize();
+        if direction.dot(forward) < 0.0 {
+            direction = -direction;
+        }
+
+        // Back along the ray to the plane of the camera, which is the
+        // camera itself for perspective projections. Orthographic rays all
+        // start on that plane.
+        let origin = a - direction * ((a - view.position).dot(forward) / direction.dot(forward));
+        Ray { origin, direction }
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
@@ -115,6 +257,40 @@ impl Camera {
         let view_proj = proj * view;
         self.uniform.view_proj = view_proj.to_cols_array_2d();
         self.uniform.inv_view_proj = view_proj.inverse().to_cols_array_2d();
-        self.uniform.view_pos = [self.position.x, self.position.y, self.position.z, 1.0];
+        let position = self.view_state().position;
+        self.uniform.view_pos = [position.x, position.y, position.z, 1.0];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec4;
+
+    use super::*;
+
+    fn depth(projection: &Projection, distance: f32) -> f32 {
+        let clip = projection.matrix(1.0, 0.0) * Vec4::new(0.0, 0.0, -distance, 1.0);
+        clip.z / clip.w
+    }
+
+    #[test]
+    fn depth_is_reversed() {
+        let infinite = Projection::default();
+        assert!((depth(&infinite, infinite.znear) - 1.0).abs() < 1e-6);
+        assert!(depth(&infinite, 10.0) > depth(&infinite, 1000.0));
+        assert!(depth(&infinite, 1.0e6) > 0.0);
+
+        let finite = Projection {
+            zfar: Some(100.0),
+            ..Default::default()
+        };
+        assert!(depth(&finite, 100.0).abs() < 1e-6);
+
+        let orthographic = Projection {
+            orthographic: Some(10.0),
+            ..Default::default()
+        };
+        assert!((depth(&orthographic, orthographic.znear) - 1.0).abs() < 1e-6);
+        assert!(depth(&orthographic, 10.0) > depth(&orthographic, 20.0));
     }
 }
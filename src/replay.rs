@@ -0,0 +1,162 @@
+use std::fs;
+
+use anyhow::{Result, bail};
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    headless::ScriptedThrow, level::Level, physics::Timestep,
+    renderer::uniform::camera::CameraState, world::World,
+};
+
+/// Everything needed to reproduce a play session, stored as RON
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Recording {
+    pub level: String,
+    pub tick_rate: f32,
+    pub throws: Vec<ScriptedThrow>,
+    /// Camera state at every tick, before the player followed it
+    pub cameras: Vec<CameraState>,
+    /// Ticks played with the noclip debug camera, in order
+    #[serde(default)]
+    pub noclip: Vec<u32>,
+    /// [World::state_hash] at the end of the session
+    pub hash: u64,
+}
+
+impl Recording {
+    pub fn load(path: &str) -> Result<Self> {
+        log::info!("Loading recording {path}");
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        log::info!("Saving recording to {path}");
+        fs::write(path, ron::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Runs the session again on a fresh world and checks the end state
+    pub fn replay(&self) -> Result<World> {
+        let mut world = World::new();
+        world.load_level(&Level::load(&self.level)?)?;
+
+        let timestep = Timestep::new(self.tick_rate);
+
+        log::info!("Replaying {} ticks", self.cameras.len());
+        for (tick, camera) in self.cameras.iter().enumerate() {
+            for throw in self.throws.iter().filter(|t| t.tick == tick as u32) {
+                world.throw_ball(throw.position, throw.direction, throw.speed);
+            }
+
+            let noclip = self.noclip.binary_search(&(tick as u32)).is_ok();
+            world.step(*camera, noclip, timestep.dt());
+        }
+
+        let hash = world.state_hash();
+        if hash != self.hash {
+            bail!(
+                "replay diverged, expected state hash {:#x} but got {hash:#x}",
+                self.hash
+            );
+        }
+
+        log::info!("Replay matches the recorded state {hash:#x}");
+        Ok(world)
+    }
+}
+
+/// Collects throws and camera states while playing
+pub struct Recorder {
+    pub path: String,
+    pub recording: Recording,
+}
+
+impl Recorder {
+    pub fn new(path: &str, level: &str, tick_rate: f32) -> Self {
+        Self {
+            path: path.to_string(),
+            recording: Recording {
+                level: level.to_string(),
+                tick_rate,
+                throws: Vec::new(),
+                cameras: Vec::new(),
+                noclip: Vec::new(),
+                hash: 0,
+            },
+        }
+    }
+
+    /// Throws are applied before the next tick, both live and in replays
+    pub fn record_throw(&mut self, position: Vec3, direction: Vec3, speed: f32) {
+        self.recording.throws.push(ScriptedThrow {
+            tick: self.recording.cameras.len() as u32,
+            position,
+            direction,
+            speed,
+        });
+    }
+
+    pub fn record_tick(&mut self, camera: CameraState, noclip: bool) {
+        if noclip {
+            let tick = self.recording.cameras.len() as u32;
+            self.recording.noclip.push(tick);
+        }
+        self.recording.cameras.push(camera);
+    }
+
+    pub fn finish(mut self, world: &World) -> Result<()> {
+        self.recording.hash = world.state_hash();
+        self.recording.save(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::DEFAULT_LEVEL;
+
+    #[test]
+    fn replay_reproduces_recorded_session() {
+        let timestep = Timestep::default();
+        let mut recorder = Recorder::new("unused.ron", DEFAULT_LEVEL, timestep.tick_rate);
+
+        let mut world = World::new();
+        world
+            .load_level(&Level::load(DEFAULT_LEVEL).unwrap())
+            .unwrap();
+
+        // Walk forward while throwing slightly to the side every 20 ticks
+        let mut camera = CameraState {
+            position: Vec3::new(0.0, 1.0, 2.0),
+            yaw: -90.0f32.to_radians(),
+            pitch: 0.0,
+        };
+
+        let mut culled = 0;
+        for tick in 0..300 {
+            if tick % 20 == 0 {
+                camera.yaw += 0.01;
+                let direction = camera.view_dir();
+                recorder.record_throw(camera.position, direction, 15.0);
+                world.throw_ball(camera.position, direction, 15.0);
+            }
+
+            camera.position.z -= 0.05;
+            // Same tick as the game, the recorded camera is the one before it
+            // A stretch of noclip, replays have to follow it
+            let noclip = (100..150).contains(&tick);
+            culled += world.step(camera, noclip, timestep.dt()).culled.len();
+            recorder.record_tick(camera, noclip);
+        }
+        assert!(culled > 0, "the walk never left objects behind");
+
+        recorder.recording.hash = world.state_hash();
+
+        let text = ron::to_string(&recorder.recording).unwrap();
+        let recording: Recording = ron::from_str(&text).unwrap();
+
+        let replayed = recording.replay().unwrap();
+        assert_eq!(replayed.state_hash(), world.state_hash());
+    }
+}
@@ -1,465 +1,291 @@
-use std::{
-    collections::HashMap,
-    fs,
-    hash::{DefaultHasher, Hash, Hasher},
-    sync::Arc,
-};
+use std::sync::Arc;
 
 use crate::{
     camera_controller::CameraController,
-    physics::Physics,
+    camera_effects::{CameraEffects, GameEvent},
+    level::{Level, LevelMeshes, hash_string_to_u64},
+    particle_effects::{DEFAULT_PARTICLES, ParticleEffects},
+    physics::Timestep,
+    player::PlayerHit,
     renderer::{
         Renderer,
-        pipeline::{
-            InstanceRaw,
-            color::{ColoredVertex, generate_sphere},
-            texture::TexturedVertex,
-        },
+        mesh::Mesh,
+        pipeline::{InstanceRaw, Pipelines, color::generate_sphere},
         texture::Texture,
+        uniform::camera::Ray,
     },
+    settings::Settings,
+    world::World,
 };
-use bimap::BiHashMap;
-use glam::{Mat3, Mat4, Vec2, Vec3};
-use gltf::{Gltf, Node, Primitive};
+use anyhow::Result;
+use glam::{Mat3, Mat4, Vec3};
 use kira::{
-    AudioManager, AudioManagerSettings, DefaultBackend, sound::static_sound::StaticSoundData,
-};
-use rapier3d::{
-    math::{Point, Vector},
-    prelude::{ColliderBuilder, ColliderHandle, RigidBodyBuilder, RigidBodyHandle},
+    AudioManager, AudioManagerSettings, DefaultBackend, Tween, sound::static_sound::StaticSoundData,
 };
-use winit::window::Window;
+use winit::{dpi::PhysicalPosition, window::Window};
+
+/// Screen edge tint when the player runs into something, and how long it fades
+const HIT_FLASH: [f32; 3] = [0.8, 0.05, 0.02];
+const HIT_FLASH_DURATION: f32 = 0.4;
 
+/// Rendering, audio and input on top of the simulated [World]
 pub struct Scene {
     pub renderer: Renderer,
-    pub physics: Physics,
     pub audio: AudioManager,
     pub camera_controller: CameraController,
-    pub objects: BiHashMap<u128, (RigidBodyHandle, ColliderHandle)>,
+    pub camera_effects: CameraEffects,
+    /// Flies through obstacles instead of stopping at the player
+    pub debug_camera: bool,
+    pub particle_effects: ParticleEffects,
+    /// Gameplay events since the last frame
+    pub events: Vec<GameEvent>,
+    /// Forward speed of the camera in the last frame, for spotting speed-ups
+    forward_speed: f32,
+    pub world: World,
+    pub level: Option<Level>,
+    /// Seconds of simulated time since the level was loaded
+    pub level_time: f32,
 }
 
 impl Scene {
-    pub fn new(window: Arc<Window>) -> Self {
-        Self {
-            renderer: pollster::block_on(Renderer::new(window)).unwrap(),
-            physics: Physics::new(),
+    pub fn new(window: Arc<Window>, settings: &Settings) -> Self {
+        let mut scene = Self {
+            renderer: pollster::block_on(Renderer::new(window, settings.renderer.clone())).unwrap(),
             audio: AudioManager::<DefaultBackend>::new(AudioManagerSettings::default()).unwrap(),
             camera_controller: CameraController::default(),
-            objects: BiHashMap::new(),
-        }
+            camera_effects: CameraEffects::default(),
+            debug_camera: false,
+            particle_effects: ParticleEffects::default(),
+            events: Vec::new(),
+            forward_speed: 0.0,
+            world: World::new(),
+            level: None,
+            level_time: 0.0,
+        };
+        scene.apply_settings(settings);
+        scene
     }
 
-    pub fn cull_instances_behind_camera(&mut self) {
-        let camera_position = self.renderer.uniforms.camera.position;
-        let camera_forward = self.renderer.uniforms.camera.calc_view_dir();
-
-        let mut to_remove = Vec::new();
-
-        for (mesh_id, mesh) in self.renderer.pipelines.color_pipeline.meshes.iter().chain(
-            self.renderer
-                .pipelines
-                .texture_pipeline
-                .meshes
-                .iter()
-                .map(|(id, (mesh, _))| (id, mesh)),
-        ) {
-            for (instance_index, instance) in mesh.instances.iter().enumerate() {
-                let model = instance.model;
-                let position = glam::Vec3::new(model[3][0], model[3][1], model[3][2]);
-
-                let to_object = position - camera_position;
-                if to_object.dot(camera_forward) < 0.0 {
-                    to_remove.push((*mesh_id, instance_index));
-                }
-            }
-        }
-
-        for (mesh_id, instance_index) in to_remove.into_iter().rev() {
-            self.remove_instance(mesh_id, instance_index);
-        }
+    /// Hands the settings to the renderer, audio and camera controller
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        self.renderer.set_settings(settings.renderer.clone());
+        self.audio
+            .main_track()
+            .set_volume(settings.audio.decibels(), Tween::default());
+        self.camera_controller.speed = settings.controls.speed;
+        self.camera_controller.sensitivity = settings.controls.mouse_sensitivity;
     }
 
-    pub fn add_gltf(&mut self, path: &str) {
-        log::info!("Adding gltf to scene");
-
-        let gltf = Gltf::from_slice(&fs::read(path).unwrap()).unwrap();
-        let mut instances: HashMap<String, Vec<InstanceRaw>> = HashMap::new();
-        let mut textured_meshes: HashMap<String, (Vec<TexturedVertex>, Vec<u16>, Vec<u8>)> =
-            HashMap::new();
-        let mut colored_meshes: HashMap<String, (Vec<ColoredVertex>, Vec<u16>, [f32; 4])> =
-            HashMap::new();
-        let mut collider_meshes: HashMap<String, (Vec<Vec3>, Vec<u16>)> = HashMap::new();
-
-        if let Some(blob) = &gltf.blob {
-            log::info!("Data collection");
-            for node in gltf.nodes() {
-                self.add_node(
-                    node,
-                    blob,
-                    &mut instances,
-                    &mut textured_meshes,
-                    &mut colored_meshes,
-                    &mut collider_meshes,
-                );
-            }
-        }
-
+    /// Uploads the level geometry to the renderer
+    pub fn add_meshes(&mut self, meshes: &LevelMeshes) -> Result<()> {
         log::info!("Processing meshes");
-        for (name, (vertices, indices, _base_color)) in colored_meshes {
+        for (name, (vertices, indices, _base_color)) in &meshes.colored {
             self.renderer.pipelines.color_pipeline.add_mesh(
                 &self.renderer.device,
-                hash_string_to_u64(&name),
-                &vertices,
-                &indices,
-                instances.get(&name).unwrap(),
+                hash_string_to_u64(name),
+                vertices,
+                indices,
+                &meshes.instances[name],
             );
         }
 
-        for (name, (vertices, indices, image_data)) in textured_meshes {
+        for (name, (vertices, indices, image_data)) in &meshes.textured {
             let texture = Texture::from_bytes(
                 &self.renderer.device,
                 &self.renderer.queue,
-                &image_data,
-                &name,
-            )
-            .unwrap();
+                image_data,
+                name,
+            )?;
 
             self.renderer.pipelines.texture_pipeline.add_mesh(
                 &self.renderer.device,
-                hash_string_to_u64(&name),
+                hash_string_to_u64(name),
                 &texture,
-                &vertices,
-                &indices,
-                instances.get(&name).unwrap(),
+                vertices,
+                indices,
+                &meshes.instances[name],
             );
         }
 
-        log::info!("Adding physics objects");
-        for (name, (positions, indices)) in collider_meshes {
-            let instances_list = instances.remove(&name).unwrap();
-
-            for (instance_index, instance) in instances_list.into_iter().enumerate() {
-                let model_matrix = Mat4::from_cols_array_2d(&instance.model);
-                let (scale, rotation, translation) = model_matrix.to_scale_rotation_translation();
-                let angvel = rotation.to_scaled_axis();
-
-                let scaled_vertices: Vec<Vec3> = positions.iter().map(|v| *v * scale).collect();
-
-                let points: Vec<Point<_>> = scaled_vertices
-                    .iter()
-                    .map(|v| Point::new(v.x, v.y, v.z))
-                    .collect();
-
-                let det = model_matrix.determinant();
-                let mut final_indices = indices.clone();
-                if det < 0.0 {
-                    for chunk in final_indices.chunks_exact_mut(3) {
-                        chunk.swap(1, 2);
-                    }
-                }
-
-                let triangles: Vec<[u32; 3]> = final_indices
-                    .chunks_exact(3)
-                    .map(|tri| [tri[0] as u32, tri[1] as u32, tri[2] as u32])
-                    .collect();
-
-                let collider = match ColliderBuilder::trimesh(points, triangles) {
-                    Ok(builder) => builder.build(),
-                    Err(e) => {
-                        log::error!("Failed to create trimesh collider for mesh {name}: {e:?}");
-                        continue;
-                    }
-                };
-
-                let rigid_body = RigidBodyBuilder::fixed()
-                    .translation(Vector::new(translation.x, translation.y, translation.z))
-                    .rotation(Vector::new(angvel.x, angvel.y, angvel.z))
-                    .build();
-
-                let rigid_body_handle = self.physics.bodies.insert(rigid_body);
-                let collider_handle = self.physics.colliders.insert_with_parent(
-                    collider,
-                    rigid_body_handle,
-                    &mut self.physics.bodies,
-                );
-
-                let mesh_id = hash_string_to_u64(&name);
-                let id = ((mesh_id as u128) << 64) | (instance_index as u128);
-
-                self.objects
-                    .insert(id, (rigid_body_handle, collider_handle));
-
-                log::info!("RigidBody of {name} created on {translation}");
-            }
-        }
-    }
-
-    pub fn add_node(
-        &mut self,
-        node: Node,
-        blob: &[u8],
-        instances: &mut HashMap<String, Vec<InstanceRaw>>,
-        textured_meshes: &mut HashMap<String, (Vec<TexturedVertex>, Vec<u16>, Vec<u8>)>,
-        colored_meshes: &mut HashMap<String, (Vec<ColoredVertex>, Vec<u16>, [f32; 4])>,
-        collider_meshes: &mut HashMap<String, (Vec<Vec3>, Vec<u16>)>,
-    ) {
-        let Some(mesh) = node.mesh() else { return };
-        let Some(name) = mesh.name() else { return };
-
-        let model_matrix = Mat3::from_mat4(Mat4::from_cols_array_2d(&node.transform().matrix()));
-        let normal_matrix = model_matrix.inverse().transpose();
-
-        if let Some((base_name, _)) = name.split_once('.') {
-            instances
-                .entry(base_name.to_string())
-                .or_default()
-                .push(InstanceRaw {
-                    model: node.transform().matrix(),
-                    normal: normal_matrix.to_cols_array_2d(),
-                });
-            return;
-        }
-
-        for primitive in mesh.primitives() {
-            self.add_primitive(
-                primitive,
-                name,
-                blob,
-                textured_meshes,
-                colored_meshes,
-                collider_meshes,
-            );
-        }
-    }
-
-    pub fn add_primitive(
-        &mut self,
-        primitive: Primitive,
-        name: &str,
-        blob: &[u8],
-        textured_meshes: &mut HashMap<String, (Vec<TexturedVertex>, Vec<u16>, Vec<u8>)>,
-        colored_meshes: &mut HashMap<String, (Vec<ColoredVertex>, Vec<u16>, [f32; 4])>,
-        collider_meshes: &mut HashMap<String, (Vec<Vec3>, Vec<u16>)>,
-    ) {
-        let reader = primitive.reader(|buffer| {
-            if buffer.index() == 0 {
-                Some(blob)
-            } else {
-                None
-            }
-        });
-
-        let indices: Vec<u16> = match reader
-            .read_indices()
-            .map(|i| i.into_u32().map(|v| v as u16))
-        {
-            Some(indices) => indices.collect(),
-            None => return,
-        };
-
-        let positions: Vec<Vec3> = reader.read_positions().unwrap().map(Vec3::from).collect();
-        if positions.is_empty() {
-            log::warn!("Mesh '{name}' has no positions, skipping");
-            return;
-        }
-
-        let normals = match reader.read_normals() {
-            Some(n) => n.map(Vec3::from).collect(),
-            None => {
-                log::warn!("Normals not found for '{name}', generating...");
-                Renderer::compute_normals(&positions, &indices)
-            }
-        };
-
-        if let Some(tex_coords) = reader
-            .read_tex_coords(0)
-            .map(|t| t.into_f32().map(Vec2::from).collect::<Vec<_>>())
-        {
-            log::info!("Finded texture coords of {name}, trying load texture info");
-
-            if let Some(texture_info) = primitive
-                .material()
-                .pbr_metallic_roughness()
-                .base_color_texture()
-            {
-                log::info!("Texture info loaded, trying get texture");
-                if let gltf::image::Source::View { view, .. } =
-                    texture_info.texture().source().source()
-                {
-                    log::info!("Try load texture mesh");
-
-                    let image_data = &blob[view.offset()..view.offset() + view.length()];
-
-                    let vertices = positions
-                        .iter()
-                        .zip(tex_coords.iter())
-                        .zip(normals.iter())
-                        .map(|((pos, uv), normal)| TexturedVertex {
-                            position: pos.to_array(),
-                            tex_coords: [uv.x, uv.y],
-                            normal: normal.to_array(),
-                        })
-                        .collect();
-
-                    collider_meshes.insert(name.to_string(), (positions, indices.clone()));
-                    textured_meshes
-                        .insert(name.to_string(), (vertices, indices, image_data.to_vec()));
-                    return;
-                }
-            }
-        }
-
-        let colors = reader
-            .read_colors(0)
-            .map(|c| c.into_rgba_f32().map(|v| [v[0], v[1], v[2]]).collect())
-            .unwrap_or_else(|| {
-                let base = primitive
-                    .material()
-                    .pbr_metallic_roughness()
-                    .base_color_factor();
-                vec![[base[0], base[1], base[2]]; positions.len()]
-            });
-
-        let vertices = positions
-            .iter()
-            .zip(colors.iter())
-            .zip(normals.iter())
-            .map(|((pos, color), normal)| ColoredVertex {
-                position: pos.to_array(),
-                color: *color,
-                normal: normal.to_array(),
-            })
-            .collect();
-
-        collider_meshes.insert(name.to_string(), (positions, indices.clone()));
-
-        colored_meshes.insert(
-            name.to_string(),
-            (
-                vertices,
-                indices,
-                primitive
-                    .material()
-                    .pbr_metallic_roughness()
-                    .base_color_factor(),
-            ),
-        );
+        Ok(())
     }
 
     pub fn init_ball(&mut self) {
-        let (vertices, indices) = generate_sphere(0.5, 16, 16, [1.0, 0.0, 0.0]);
+        let (vertices, indices) =
+            generate_sphere(self.world.balls.settings.radius, 16, 16, [1.0, 0.0, 0.0]);
+
+        // One instance per pool slot, parked balls have a zeroed (invisible) transform
         self.renderer.pipelines.color_pipeline.add_mesh(
             &self.renderer.device,
-            hash_string_to_u64("ball"),
+            self.world.balls.mesh_id,
             &vertices,
             &indices,
-            &[InstanceRaw {
-                model: Default::default(),
-                normal: Default::default(),
-            }],
+            &vec![bytemuck::Zeroable::zeroed(); self.world.balls.settings.max_count],
         );
     }
 
     pub fn remove_instance(&mut self, mesh_id: u64, instance_index: usize) {
-        if let Some(mesh) = self
-            .renderer
-            .pipelines
+        self.remove_mesh_instance(mesh_id, instance_index);
+        self.world.remove_object(mesh_id, instance_index);
+    }
+
+    /// Level mesh from either the color or the texture pipeline
+    fn level_mesh(pipelines: &mut Pipelines, mesh_id: u64) -> Option<&mut Mesh> {
+        pipelines
             .color_pipeline
             .meshes
             .get_mut(&mesh_id)
-            .or(self
-                .renderer
-                .pipelines
+            .or(pipelines
                 .texture_pipeline
                 .meshes
                 .get_mut(&mesh_id)
                 .map(|(m, _)| m))
-        {
-            let instance_count_before = mesh.instances.len();
+    }
 
-            if instance_index >= instance_count_before {
-                return;
-            }
+    fn remove_mesh_instance(&mut self, mesh_id: u64, instance_index: usize) {
+        let renderer = &mut self.renderer;
+        if let Some(mesh) = Self::level_mesh(&mut renderer.pipelines, mesh_id)
+            && instance_index < mesh.instances.len()
+        {
+            mesh.remove_instance(&renderer.device, &renderer.queue, instance_index);
+        }
+    }
 
-            let last_index = instance_count_before - 1;
+    /// Returns the ball slot, `None` when the player is out of balls
+    pub fn spawn_ball_instance(
+        &mut self,
+        position: Vec3,
+        direction: Vec3,
+        speed: f32,
+    ) -> Option<usize> {
+        let slot = self.world.throw_ball(position, direction, speed);
+        if slot.is_some() {
+            self.events.push(GameEvent::BallThrown);
+        }
+        slot
+    }
 
-            mesh.remove_instance(&self.renderer.device, &self.renderer.queue, instance_index);
+    /// Ray from the camera through a point on the window, or the screen
+    /// center without one
+    pub fn pointer_ray(&self, pointer: Option<PhysicalPosition<f64>>) -> Ray {
+        let camera = &self.renderer.uniforms.camera;
+        match pointer {
+            Some(pointer) => camera.screen_to_world_ray(pointer, self.renderer.output_size()),
+            None => Ray {
+                origin: camera.position,
+                direction: camera.calc_view_dir(),
+            },
+        }
+    }
 
-            let user_data_removed = ((mesh_id as u128) << 64) | (instance_index as u128);
+    /// Advances the simulation by one fixed tick. Culling happens here too,
+    /// so it follows the tick clock and replays stay deterministic.
+    pub fn tick(&mut self, timestep: &Timestep) {
+        let camera = &mut self.renderer.uniforms.camera;
+        let step = self
+            .world
+            .step(camera.state(), self.debug_camera, timestep.dt());
+        // Obstacles stop the camera too
+        if step.position != camera.position {
+            camera.position = step.position;
+            camera.update_uniform();
+        }
+        if let Some(hit) = &step.hit {
+            self.player_hit(hit);
+        }
+        for position in step.checkpoints {
+            self.events.push(GameEvent::Checkpoint { position });
+        }
 
-            if let Some((_, (rigid_body, collider))) =
-                self.objects.remove_by_left(&user_data_removed)
+        for slot in step.despawned {
+            if let Some(mesh) = self
+                .renderer
+                .pipelines
+                .color_pipeline
+                .meshes
+                .get_mut(&self.world.balls.mesh_id)
             {
-                self.physics.colliders.remove(
-                    collider,
-                    &mut self.physics.islands,
-                    &mut self.physics.bodies,
-                    true,
-                );
-                self.physics.bodies.remove(
-                    rigid_body,
-                    &mut self.physics.islands,
-                    &mut self.physics.colliders,
-                    &mut self.physics.impulse_joints,
-                    &mut self.physics.multibody_joints,
-                    true,
-                );
+                mesh.update_instance(&self.renderer.queue, slot, &bytemuck::Zeroable::zeroed());
             }
+        }
+        for impact in &self.world.physics.impacts {
+            self.events.push(GameEvent::BallImpact {
+                position: impact.position,
+                normal: impact.normal,
+                speed: impact.speed,
+            });
+        }
+        for (mesh_id, instance_index) in step.culled {
+            self.remove_mesh_instance(mesh_id, instance_index);
+        }
 
-            if instance_index != last_index {
-                let old_user_data_last = ((mesh_id as u128) << 64) | (last_index as u128);
-                if let Some((_, (rigid_body, a))) = self.objects.remove_by_left(&old_user_data_last)
-                {
-                    if let Some(body) = self.physics.bodies.get_mut(rigid_body) {
-                        body.user_data = user_data_removed;
-                    }
-                    self.objects.insert(user_data_removed, (rigid_body, a));
-                }
+        self.level_time += timestep.dt();
+        self.animate_level();
+    }
+
+    fn player_hit(&mut self, hit: &PlayerHit) {
+        for &(mesh_id, instance_index) in &hit.shattered {
+            if let Some(instance) = Self::level_mesh(&mut self.renderer.pipelines, mesh_id)
+                .and_then(|mesh| mesh.instances.get(instance_index))
+            {
+                let position = Vec3::from_slice(&instance.model[3][..3]);
+                self.events.push(GameEvent::GlassBroken { position });
             }
+            self.remove_mesh_instance(mesh_id, instance_index);
         }
+        self.events.push(GameEvent::PlayerHit {
+            position: hit.position,
+        });
     }
 
-    pub fn spawn_ball_instance(
-        &mut self,
-        position: Vec3,
-        direction: Vec3,
-        speed: f32,
-        radius: f32,
-    ) {
-        let mesh_id = hash_string_to_u64("ball");
-        let mesh = self
-            .renderer
-            .pipelines
-            .color_pipeline
-            .meshes
-            .get_mut(&mesh_id)
-            .unwrap();
-
-        let transform = Mat4::from_translation(position);
-        let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
+    /// Puts the camera effects for this frame on top of where the
+    /// controller and the ticks left the camera, and emits particles and
+    /// screen flashes
+    pub fn update_effects(&mut self, dt: f32) {
+        let forward_speed = self.camera_controller.forward_speed();
+        if forward_speed > self.forward_speed {
+            self.events
+                .push(GameEvent::SpeedUp(forward_speed - self.forward_speed));
+        }
+        self.forward_speed = forward_speed;
 
-        mesh.add_instance(
-            &self.renderer.device,
-            &self.renderer.queue,
-            &InstanceRaw {
-                model: transform.to_cols_array_2d(),
-                normal: normal_matrix.to_cols_array_2d(),
-            },
-        );
+        let camera = &mut self.renderer.uniforms.camera;
+        let pipelines = &mut self.renderer.pipelines;
+        for event in self.events.drain(..) {
+            if let GameEvent::PlayerHit { .. } = event {
+                pipelines.post_pipeline.flash(HIT_FLASH, HIT_FLASH_DURATION);
+            }
+            self.particle_effects.handle(
+                event,
+                &mut pipelines.particle_pipeline,
+                &self.renderer.queue,
+            );
+            self.camera_effects.handle(event, camera);
+        }
+        self.camera_effects.update(dt, camera);
+        camera.update_uniform();
+    }
 
-        let instance_id = mesh.instances.len() as u128 - 1;
-        let id = ((mesh_id as u128) << 64) | instance_id;
+    /// Applies the level's keyframed render settings for the current time
+    fn animate_level(&mut self) {
+        let Some(level) = &self.level else { return };
 
-        let velocity = direction * speed;
-        self.objects
-            .insert(id, self.physics.create_ball(id, position, velocity, radius));
+        if let Some(bloom) = level.bloom.sample(self.level_time) {
+            self.renderer.pipelines.bloom_pipeline.settings = bloom;
+        }
     }
 
-    pub fn update_objects(&mut self) {
-        for (_, body) in self.physics.bodies.iter() {
+    /// Writes interpolated dynamic body transforms into the instance buffers
+    pub fn update_objects(&mut self, alpha: f32) {
+        for (handle, body) in self.world.physics.bodies.iter() {
             // Update dynamic objects
-            if body.user_data != 0 {
-                let model = body.position().to_homogeneous().into();
+            if body.user_data != 0 && body.is_enabled() {
+                let model = self
+                    .world
+                    .physics
+                    .interpolated_position(handle, alpha)
+                    .to_homogeneous()
+                    .into();
                 let normal = Mat3::from_mat4(Mat4::from_cols_array_2d(&model))
                     .inverse()
                     .transpose()
@@ -480,18 +306,31 @@ impl Scene {
         }
     }
 
-    pub fn init_level(&mut self) {
+    pub fn init_level(&mut self, path: &str) -> Result<()> {
+        let level = Level::load(path)?;
+        let meshes = self.world.load_level(&level)?;
+
         self.init_ball();
-        self.add_gltf("map.glb");
+        self.add_meshes(&meshes)?;
 
-        self.audio
-            .play(StaticSoundData::from_file("assets/music/12.ogg").unwrap())
-            .unwrap();
-    }
-}
+        if let Some(music) = &level.music {
+            self.audio.play(StaticSoundData::from_file(music)?)?;
+        }
 
-fn hash_string_to_u64(s: &str) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    s.hash(&mut hasher);
-    hasher.finish()
+        let renderer = &mut self.renderer;
+        renderer.pipelines.background_pipeline.set_background(
+            &renderer.device,
+            &renderer.queue,
+            level.background.clone(),
+        )?;
+        renderer.pipelines.post_pipeline.settings = level.post;
+        renderer.pipelines.particle_pipeline.gravity = self.world.physics.settings.gravity;
+        self.particle_effects =
+            ParticleEffects::load(level.particles.as_deref().unwrap_or(DEFAULT_PARTICLES))?;
+        self.level = Some(level);
+        self.level_time = 0.0;
+        self.animate_level();
+
+        Ok(())
+    }
 }
@@ -0,0 +1,361 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gamepad::GamepadSettings,
+    input::Bindings,
+    renderer::{resolution::RENDER_SCALE_RANGE, settings::RendererSettings},
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowSettings {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    /// Linear master volume from 0 to 1
+    pub volume: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self { volume: 1.0 }
+    }
+}
+
+impl AudioSettings {
+    /// Master volume for kira, which works in decibels
+    pub fn decibels(&self) -> f32 {
+        if self.volume <= 0.0 {
+            kira::Decibels::SILENCE.0
+        } else {
+            (20.0 * self.volume.log10()).max(kira::Decibels::SILENCE.0)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlSettings {
+    /// Camera movement in units per second
+    pub speed: f32,
+    pub mouse_sensitivity: f32,
+}
+
+impl Default for ControlSettings {
+    fn default() -> Self {
+        Self {
+            speed: 4.0,
+            mouse_sensitivity: 5.0,
+        }
+    }
+}
+
+/// User settings, stored as RON in the platform config directory
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub window: WindowSettings,
+    pub renderer: RendererSettings,
+    pub audio: AudioSettings,
+    pub controls: ControlSettings,
+    pub bindings: Bindings,
+    pub gamepad: GamepadSettings,
+}
+
+impl Settings {
+    /// `settings.ron` in the platform config directory, `None` on platforms
+    /// without one
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("smashbit").join("settings.ron"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = fs::read_to_string(path)?;
+        let mut settings: Self =
+            ron::from_str(&source).with_context(|| format!("Invalid {}", path.display()))?;
+        settings.validate();
+        Ok(settings)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let source = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, source)?;
+        Ok(())
+    }
+
+    /// Replaces values that are out of range with the closest valid ones,
+    /// or the default where there is none
+    pub fn validate(&mut self) {
+        let defaults = Self::default();
+
+        let window = &mut self.window;
+        window.width = window.width.clamp(320, 16384);
+        window.height = window.height.clamp(240, 16384);
+
+        let renderer = &mut self.renderer;
+        renderer.frame_cap = renderer
+            .frame_cap
+            .filter(|fps| fps.is_finite() && *fps >= 1.0);
+        renderer.dynamic_resolution = renderer
+            .dynamic_resolution
+            .filter(|fps| fps.is_finite() && *fps >= 1.0);
+        renderer.max_frame_latency = renderer.max_frame_latency.clamp(1, 3);
+        if !renderer.msaa.is_power_of_two() {
+            warn_invalid("renderer.msaa", renderer.msaa);
+            renderer.msaa = defaults.renderer.msaa;
+        }
+        renderer.render_scale = valid_f32(
+            "renderer.render_scale",
+            renderer.render_scale,
+            defaults.renderer.render_scale,
+        )
+        .clamp(*RENDER_SCALE_RANGE.start(), *RENDER_SCALE_RANGE.end());
+        renderer.fov =
+            valid_f32("renderer.fov", renderer.fov, defaults.renderer.fov).clamp(30.0, 120.0);
+
+        self.audio.volume =
+            valid_f32("audio.volume", self.audio.volume, defaults.audio.volume).clamp(0.0, 1.0);
+
+        let controls = &mut self.controls;
+        for (name, value, default) in [
+            (
+                "controls.speed",
+                &mut controls.speed,
+                defaults.controls.speed,
+            ),
+            (
+                "controls.mouse_sensitivity",
+                &mut controls.mouse_sensitivity,
+                defaults.controls.mouse_sensitivity,
+            ),
+        ] {
+            *value = valid_f32(name, *value, default);
+            if *value <= 0.0 {
+                warn_invalid(name, *value);
+                *value = default;
+            }
+        }
+
+        let gamepad = &mut self.gamepad;
+        let gamepad_defaults = defaults.gamepad;
+        for (name, value, default, min, max) in [
+            (
+                "gamepad.deadzone",
+                &mut gamepad.deadzone,
+                gamepad_defaults.deadzone,
+                0.0,
+                0.9,
+            ),
+            (
+                "gamepad.outer_deadzone",
+                &mut gamepad.outer_deadzone,
+                gamepad_defaults.outer_deadzone,
+                0.0,
+                0.5,
+            ),
+            (
+                "gamepad.aim_curve",
+                &mut gamepad.aim_curve,
+                gamepad_defaults.aim_curve,
+                0.2,
+                5.0,
+            ),
+            (
+                "gamepad.aim_speed",
+                &mut gamepad.aim_speed,
+                gamepad_defaults.aim_speed,
+                0.1,
+                20.0,
+            ),
+            (
+                "gamepad.aim_acceleration",
+                &mut gamepad.aim_acceleration,
+                gamepad_defaults.aim_acceleration,
+                1.0,
+                5.0,
+            ),
+            (
+                "gamepad.acceleration_time",
+                &mut gamepad.acceleration_time,
+                gamepad_defaults.acceleration_time,
+                0.0,
+                5.0,
+            ),
+            (
+                "gamepad.trigger_threshold",
+                &mut gamepad.trigger_threshold,
+                gamepad_defaults.trigger_threshold,
+                0.05,
+                1.0,
+            ),
+        ] {
+            *value = valid_f32(name, *value, default).clamp(min, max);
+        }
+    }
+}
+
+fn warn_invalid(name: &str, value: impl std::fmt::Display) {
+    log::warn!("Invalid setting {name} = {value}, using the default");
+}
+
+fn valid_f32(name: &str, value: f32, default: f32) -> f32 {
+    if value.is_finite() {
+        value
+    } else {
+        warn_invalid(name, value);
+        default
+    }
+}
+
+/// The settings file and what was last read from it. Edits to the file
+/// while the game runs are picked up by [SettingsFile::poll].
+pub struct SettingsFile {
+    pub path: Option<PathBuf>,
+    pub settings: Settings,
+    modified: Option<SystemTime>,
+}
+
+impl SettingsFile {
+    /// Loads `path`, or writes the defaults there when it doesn't exist yet.
+    /// Broken files are left alone for the user to fix.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let mut file = Self {
+            path,
+            settings: Settings::default(),
+            modified: None,
+        };
+
+        let Some(path) = &file.path else {
+            log::warn!("No config directory, using default settings");
+            return file;
+        };
+
+        if path.exists() {
+            match Settings::load(path) {
+                Ok(settings) => file.settings = settings,
+                Err(e) => log::error!("Failed to load settings, using defaults: {e:#}"),
+            }
+        } else if let Err(e) = file.settings.save(path) {
+            log::error!("Failed to save default settings: {e}");
+        }
+        log::info!("Settings file {}", path.display());
+
+        file.modified = file.modified_time();
+        file
+    }
+
+    fn modified_time(&self) -> Option<SystemTime> {
+        let path = self.path.as_ref()?;
+        fs::metadata(path).and_then(|meta| meta.modified()).ok()
+    }
+
+    /// Reloads the file if it changed on disk. Returns the new settings when
+    /// they differ from the current ones.
+    pub fn poll(&mut self) -> Option<&Settings> {
+        let modified = self.modified_time();
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        let settings = match Settings::load(self.path.as_ref()?) {
+            Ok(settings) => settings,
+            Err(e) => {
+                log::error!("Failed to reload settings: {e:#}");
+                return None;
+            }
+        };
+        if settings == self.settings {
+            return None;
+        }
+
+        log::info!("Settings changed");
+        self.settings = settings;
+        Some(&self.settings)
+    }
+
+    /// Writes the current settings
+    pub fn save(&mut self) {
+        let Some(path) = &self.path else { return };
+
+        if let Err(e) = self.settings.save(path) {
+            log::error!("Failed to save settings: {e}");
+        }
+        self.modified = self.modified_time();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_file_uses_defaults() {
+        let settings: Settings = ron::from_str("(audio: (volume: 0.5))").unwrap();
+
+        assert_eq!(settings.audio.volume, 0.5);
+        assert_eq!(settings.controls, ControlSettings::default());
+        assert_eq!(settings.renderer, RendererSettings::default());
+    }
+
+    #[test]
+    fn validate_fixes_bad_values() {
+        let mut settings = Settings::default();
+        settings.window.width = 0;
+        settings.renderer.msaa = 3;
+        settings.renderer.render_scale = f32::NAN;
+        settings.renderer.frame_cap = Some(0.0);
+        settings.audio.volume = 4.0;
+        settings.controls.mouse_sensitivity = -1.0;
+        settings.validate();
+
+        let defaults = Settings::default();
+        assert_eq!(settings.window.width, 320);
+        assert_eq!(settings.renderer.msaa, defaults.renderer.msaa);
+        assert_eq!(settings.renderer.render_scale, 1.0);
+        assert_eq!(settings.renderer.frame_cap, None);
+        assert_eq!(settings.audio.volume, 1.0);
+        assert_eq!(
+            settings.controls.mouse_sensitivity,
+            defaults.controls.mouse_sensitivity
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("smashbit_settings_{}.ron", std::process::id()));
+
+        let mut settings = Settings::default();
+        settings.renderer.vsync = false;
+        settings.renderer.frame_cap = Some(144.0);
+        settings.save(&path).unwrap();
+
+        assert_eq!(Settings::load(&path).unwrap(), settings);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    f32::consts::{PI, TAU},
+};
+
+use glam::{Vec2, Vec3};
+use winit::{
+    dpi::PhysicalPosition,
+    event::{Touch, TouchPhase},
+};
+
+/// Seconds a finger has to stay down before aim assist kicks in
+const HOLD_TIME: f32 = 0.25;
+/// Pixels a finger may drift and still count as held in place
+const HOLD_SLOP: f64 = 24.0;
+/// How fast the camera turns towards a held finger, per second
+const ASSIST_RATE: f32 = 4.0;
+
+struct Finger {
+    start: PhysicalPosition<f64>,
+    position: PhysicalPosition<f64>,
+    held: f32,
+    /// World direction under the finger when the hold started
+    target: Option<Vec3>,
+}
+
+impl Finger {
+    fn is_holding(&self) -> bool {
+        let (dx, dy) = (
+            self.position.x - self.start.x,
+            self.position.y - self.start.y,
+        );
+        self.held >= HOLD_TIME && dx.hypot(dy) <= HOLD_SLOP
+    }
+}
+
+/// Tracks fingers on a touchscreen. Every finger that comes down throws at
+/// its point, so several fingers throw in quick succession. A finger held in
+/// place turns the camera towards what was under it when the hold started.
+#[derive(Default)]
+pub struct Touches {
+    fingers: HashMap<u64, Finger>,
+    /// Finger that came down last, it steers the aim assist
+    newest: Option<u64>,
+}
+
+impl Touches {
+    /// Returns where to throw when the touch starts a throw
+    pub fn handle(&mut self, touch: &Touch) -> Option<PhysicalPosition<f64>> {
+        match touch.phase {
+            TouchPhase::Started => {
+                self.fingers.insert(
+                    touch.id,
+                    Finger {
+                        start: touch.location,
+                        position: touch.location,
+                        held: 0.0,
+                        target: None,
+                    },
+                );
+                self.newest = Some(touch.id);
+                Some(touch.location)
+            }
+            TouchPhase::Moved => {
+                if let Some(finger) = self.fingers.get_mut(&touch.id) {
+                    finger.position = touch.location;
+                }
+                None
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.fingers.remove(&touch.id);
+                if self.newest == Some(touch.id) {
+                    self.newest = None;
+                }
+                None
+            }
+        }
+    }
+
+    /// Advances the hold timers, returns the direction to aim assist
+    /// towards. `aim` turns the finger's point into a world direction, it's
+    /// only asked once per hold so the target stays put while the camera
+    /// turns.
+    pub fn update(
+        &mut self,
+        dt: f32,
+        aim: impl FnOnce(PhysicalPosition<f64>) -> Vec3,
+    ) -> Option<Vec3> {
+        for finger in self.fingers.values_mut() {
+            finger.held += dt;
+        }
+
+        let finger = self.newest.and_then(|id| self.fingers.get_mut(&id))?;
+        if !finger.is_holding() {
+            finger.target = None;
+            return None;
+        }
+        Some(*finger.target.get_or_insert_with(|| aim(finger.position)))
+    }
+
+    /// Forgets all fingers, when the window loses focus ends never arrive
+    pub fn clear(&mut self) {
+        self.fingers.clear();
+        self.newest = None;
+    }
+}
+
+/// Camera turn as (yaw, pitch) that eases a camera at `yaw` and `pitch`
+/// towards looking along `direction`
+pub fn aim_assist(direction: Vec3, yaw: f32, pitch: f32, dt: f32) -> Vec2 {
+    let target_yaw = direction.z.atan2(direction.x);
+    let target_pitch = direction.y.clamp(-1.0, 1.0).asin();
+
+    // The short way round
+    let yaw_delta = (target_yaw - yaw + PI).rem_euclid(TAU) - PI;
+    let pitch_delta = target_pitch - pitch;
+
+    Vec2::new(yaw_delta, pitch_delta) * (1.0 - (-ASSIST_RATE * dt).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::event::{DeviceId, Force};
+
+    use crate::renderer::uniform::camera::CameraState;
+
+    use super::*;
+
+    fn touch(id: u64, phase: TouchPhase, x: f64) -> Touch {
+        Touch {
+            device_id: DeviceId::dummy(),
+            phase,
+            location: PhysicalPosition::new(x, 10.0),
+            force: None::<Force>,
+            id,
+        }
+    }
+
+    #[test]
+    fn every_finger_throws() {
+        let mut touches = Touches::default();
+
+        assert!(
+            touches
+                .handle(&touch(0, TouchPhase::Started, 10.0))
+                .is_some()
+        );
+        assert!(
+            touches
+                .handle(&touch(1, TouchPhase::Started, 50.0))
+                .is_some()
+        );
+        assert!(touches.handle(&touch(0, TouchPhase::Moved, 12.0)).is_none());
+        assert!(touches.handle(&touch(0, TouchPhase::Ended, 12.0)).is_none());
+    }
+
+    #[test]
+    fn hold_needs_time_and_a_still_finger() {
+        let mut touches = Touches::default();
+        touches.handle(&touch(0, TouchPhase::Started, 10.0));
+
+        let aim = |position: PhysicalPosition<f64>| Vec3::new(position.x as f32, 0.0, -1.0);
+        assert!(touches.update(0.1, aim).is_none());
+        assert_eq!(touches.update(0.2, aim), Some(Vec3::new(10.0, 0.0, -1.0)));
+
+        touches.handle(&touch(0, TouchPhase::Moved, 100.0));
+        assert!(touches.update(0.1, aim).is_none());
+    }
+
+    #[test]
+    fn assist_turns_the_short_way() {
+        // Yaw isn't wrapped, a full turn further +X is still just ahead
+        let turn = aim_assist(Vec3::X, TAU - 0.1, 0.0, 1.0);
+        assert!(turn.x > 0.0 && turn.x < 0.1);
+        assert_eq!(turn.y, 0.0);
+    }
+
+    #[test]
+    fn held_finger_converges_on_its_target() {
+        let mut touches = Touches::default();
+        touches.handle(&touch(0, TouchPhase::Started, 10.0));
+        let mut camera = CameraState {
+            position: Vec3::ZERO,
+            yaw: 0.0,
+            pitch: 0.0,
+        };
+        // The finger stays on a pixel a fixed angle off the view direction
+        let offset = |camera: &CameraState| CameraState {
+            yaw: camera.yaw + 0.5,
+            pitch: camera.pitch + 0.2,
+            ..*camera
+        };
+        let target = offset(&camera);
+
+        let dt = 1.0 / 60.0;
+        let error = |camera: &CameraState| {
+            (target.yaw - camera.yaw).abs() + (target.pitch - camera.pitch).abs()
+        };
+        let mut last_error = error(&camera);
+        for frame in 0..120 {
+            let Some(direction) = touches.update(dt, |_| offset(&camera).view_dir()) else {
+                continue;
+            };
+            let turn = aim_assist(direction, camera.yaw, camera.pitch, dt);
+            camera.yaw += turn.x;
+            camera.pitch += turn.y;
+
+            let error = error(&camera);
+            assert!(
+                error <= last_error,
+                "error grew to {error} on frame {frame}"
+            );
+            last_error = error;
+        }
+        assert!(
+            last_error < 0.01,
+            "still {last_error} off after two seconds"
+        );
+    }
+}
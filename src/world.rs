@@ -0,0 +1,380 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use bimap::BiHashMap;
+use glam::{Mat4, Quat, Vec3};
+use rapier3d::prelude::{Ball, ColliderHandle, RigidBodyHandle};
+
+use crate::{
+    ball_pool::{BallPool, BallPoolSettings},
+    level::{Level, LevelMeshes, hash_string_to_u64},
+    physics::{Physics, groups},
+    player::{Player, PlayerHit, PlayerSettings},
+    renderer::uniform::camera::CameraState,
+};
+
+/// How close the player has to get to a checkpoint to reach it
+const CHECKPOINT_RADIUS: f32 = 3.0;
+
+/// What changed during a [World::step]
+pub struct Step {
+    /// Where the player ended up, obstacles may keep it behind the camera
+    pub position: Vec3,
+    pub hit: Option<PlayerHit>,
+    pub checkpoints: Vec<Vec3>,
+    /// Ball slots that were despawned
+    pub despawned: Vec<usize>,
+    /// `(mesh_id, instance_index)` of the objects culled behind the player,
+    /// in removal order
+    pub culled: Vec<(u64, usize)>,
+}
+
+/// Simulation state of a level. Doesn't depend on a window or GPU,
+/// so it can be driven headless.
+pub struct World {
+    pub physics: Physics,
+    pub objects: BiHashMap<u128, (RigidBodyHandle, ColliderHandle)>,
+    pub balls: BallPool,
+    pub player: Player,
+    /// Meshes that shatter when the player runs into them
+    pub glass: HashSet<u64>,
+    /// Checkpoints the player hasn't reached yet
+    pub checkpoints: Vec<Vec3>,
+    /// Instance count of every level mesh, mirrors the renderer meshes so
+    /// that removals keep the same instance indices on both sides
+    pub instance_counts: HashMap<u64, usize>,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl World {
+    pub fn new() -> Self {
+        let mut physics = Physics::new();
+        let balls = BallPool::new(
+            &mut physics,
+            hash_string_to_u64("ball"),
+            BallPoolSettings::default(),
+        );
+        let player = Player::new(&mut physics, PlayerSettings::default());
+
+        Self {
+            physics,
+            objects: BiHashMap::new(),
+            balls,
+            player,
+            glass: HashSet::new(),
+            checkpoints: Vec::new(),
+            instance_counts: HashMap::new(),
+        }
+    }
+
+    /// Applies the level physics settings and builds colliders from its map.
+    /// The meshes are returned so a renderer can upload them.
+    pub fn load_level(&mut self, level: &Level) -> Result<LevelMeshes> {
+        self.physics.apply_settings(level.physics);
+        self.player.apply_settings(&mut self.physics, level.player);
+        self.glass = level
+            .glass
+            .iter()
+            .map(|name| hash_string_to_u64(name))
+            .collect();
+        self.checkpoints = level.checkpoints.clone();
+
+        let meshes = LevelMeshes::load(&level.map)?;
+        self.add_colliders(&meshes);
+
+        Ok(meshes)
+    }
+
+    pub fn add_colliders(&mut self, meshes: &LevelMeshes) {
+        log::info!("Adding physics objects");
+        for (name, (positions, indices)) in &meshes.colliders {
+            let Some(instances) = meshes.instances.get(name) else {
+                continue;
+            };
+
+            self.instance_counts
+                .insert(hash_string_to_u64(name), instances.len());
+
+            for (instance_index, instance) in instances.iter().enumerate() {
+                let model_matrix = Mat4::from_cols_array_2d(&instance.model);
+                let (scale, rotation, translation) = model_matrix.to_scale_rotation_translation();
+
+                let scaled_vertices: Vec<Vec3> = positions.iter().map(|v| *v * scale).collect();
+
+                let det = model_matrix.determinant();
+                let mut final_indices = indices.clone();
+                if det < 0.0 {
+                    for chunk in final_indices.chunks_exact_mut(3) {
+                        chunk.swap(1, 2);
+                    }
+                }
+
+                let triangles: Vec<[u32; 3]> = final_indices
+                    .chunks_exact(3)
+                    .map(|tri| [tri[0] as u32, tri[1] as u32, tri[2] as u32])
+                    .collect();
+
+                let mesh_id = hash_string_to_u64(name);
+                let id = ((mesh_id as u128) << 64) | (instance_index as u128);
+
+                let (rigid_body_handle, collider_handle) = match self.physics.create_trimesh(
+                    id,
+                    translation,
+                    rotation,
+                    &scaled_vertices,
+                    triangles,
+                ) {
+                    Ok(handles) => handles,
+                    Err(e) => {
+                        log::error!("Failed to create trimesh collider for mesh {name}: {e:?}");
+                        continue;
+                    }
+                };
+
+                self.objects
+                    .insert(id, (rigid_body_handle, collider_handle));
+
+                log::info!("RigidBody of {name} created on {translation}");
+            }
+        }
+
+        self.physics.update_queries();
+    }
+
+    /// Throws one of the player's balls, `None` when they ran out
+    pub fn throw_ball(&mut self, position: Vec3, direction: Vec3, speed: f32) -> Option<usize> {
+        if !self.player.take_ball() {
+            log::info!("Out of balls");
+            return None;
+        }
+
+        Some(
+            self.balls
+                .spawn(&mut self.physics, position, direction * speed),
+        )
+    }
+
+    /// Moves the player towards the camera at `target` before the next tick.
+    /// Returns where the player ended up and the hit when it ran into
+    /// something, which shatters the glass in front of it.
+    pub fn move_player(
+        &mut self,
+        target: Vec3,
+        forward: Vec3,
+        dt: f32,
+    ) -> (Vec3, Option<PlayerHit>) {
+        let (position, obstacle) = self.player.move_to(&mut self.physics, target, dt);
+        let Some(id) = obstacle else {
+            return (position, None);
+        };
+
+        let radius = self.player.settings.shatter_distance * 0.5;
+        let mut ids = self.physics.intersect_shape(
+            &Ball::new(radius),
+            position + forward.normalize_or_zero() * radius,
+            Quat::IDENTITY,
+            groups::LEVEL,
+        );
+        ids.push(id);
+
+        let glass = ids
+            .into_iter()
+            .map(|id| ((id >> 64) as u64, id as u64 as usize))
+            .filter(|(mesh_id, _)| self.glass.contains(mesh_id))
+            .collect();
+        let shattered = self.remove_objects(glass);
+
+        log::info!(
+            "Player hit, {} balls left, {} panes shattered",
+            self.player.balls,
+            shattered.len()
+        );
+        (
+            position,
+            Some(PlayerHit {
+                id,
+                position,
+                shattered,
+            }),
+        )
+    }
+
+    /// One tick of a play session with the camera at `camera`. The game and
+    /// replays both go through here, so they simulate the same world. With
+    /// `noclip` the player goes wherever the debug camera is, without hits.
+    pub fn step(&mut self, camera: CameraState, noclip: bool, dt: f32) -> Step {
+        let forward = camera.view_dir();
+        let (position, hit) = if noclip {
+            self.player.teleport(&mut self.physics, camera.position);
+            (camera.position, None)
+        } else {
+            self.move_player(camera.position, forward, dt)
+        };
+        let checkpoints = self.reach_checkpoints(position);
+        let despawned = self.tick(dt);
+        // Where the camera ends up after the tick
+        let culled = self.cull_behind(position, forward);
+
+        Step {
+            position,
+            hit,
+            checkpoints,
+            despawned,
+            culled,
+        }
+    }
+
+    /// Advances the simulation by one fixed tick and returns the ball slots
+    /// that were despawned
+    pub fn tick(&mut self, dt: f32) -> Vec<usize> {
+        self.physics.step(dt);
+        self.balls.update(&mut self.physics, dt)
+    }
+
+    /// Removes the level objects behind the camera and returns their
+    /// `(mesh_id, instance_index)` in removal order
+    pub fn cull_behind(&mut self, position: Vec3, forward: Vec3) -> Vec<(u64, usize)> {
+        let to_remove = self
+            .objects
+            .iter()
+            .filter(|(_, (rigid_body, _))| {
+                let translation = self.physics.bodies[*rigid_body].translation();
+                let to_object = Vec3::new(translation.x, translation.y, translation.z) - position;
+                to_object.dot(forward) < 0.0
+            })
+            .map(|(id, _)| ((id >> 64) as u64, *id as u64 as usize))
+            .collect();
+
+        self.remove_objects(to_remove)
+    }
+
+    /// Checkpoints the player at `position` just reached, each one is
+    /// reached once
+    pub fn reach_checkpoints(&mut self, position: Vec3) -> Vec<Vec3> {
+        let (reached, remaining) = self
+            .checkpoints
+            .iter()
+            .partition(|checkpoint| checkpoint.distance(position) <= CHECKPOINT_RADIUS);
+        self.checkpoints = remaining;
+        reached
+    }
+
+    /// Removes several level objects and returns them in removal order
+    fn remove_objects(&mut self, mut objects: Vec<(u64, usize)>) -> Vec<(u64, usize)> {
+        // Highest index first, so swap removal never moves a pending object
+        objects.sort_unstable_by(|a, b| b.cmp(a));
+        objects.dedup();
+
+        for &(mesh_id, instance_index) in &objects {
+            self.remove_object(mesh_id, instance_index);
+        }
+        if !objects.is_empty() {
+            self.physics.update_queries();
+        }
+
+        objects
+    }
+
+    /// Removes a level object and moves the last instance of its mesh into
+    /// the freed index, like [crate::renderer::mesh::Mesh::remove_instance]
+    pub fn remove_object(&mut self, mesh_id: u64, instance_index: usize) {
+        let Some(count) = self.instance_counts.get_mut(&mesh_id) else {
+            return;
+        };
+        if instance_index >= *count {
+            return;
+        }
+
+        *count -= 1;
+        let last_index = *count;
+
+        let user_data_removed = ((mesh_id as u128) << 64) | (instance_index as u128);
+
+        if let Some((_, (rigid_body, collider))) = self.objects.remove_by_left(&user_data_removed) {
+            self.physics.colliders.remove(
+                collider,
+                &mut self.physics.islands,
+                &mut self.physics.bodies,
+                true,
+            );
+            self.physics.bodies.remove(
+                rigid_body,
+                &mut self.physics.islands,
+                &mut self.physics.colliders,
+                &mut self.physics.impulse_joints,
+                &mut self.physics.multibody_joints,
+                true,
+            );
+        }
+
+        if instance_index != last_index {
+            let old_user_data_last = ((mesh_id as u128) << 64) | (last_index as u128);
+            if let Some((_, (rigid_body, collider))) =
+                self.objects.remove_by_left(&old_user_data_last)
+            {
+                if let Some(collider) = self.physics.colliders.get_mut(collider) {
+                    collider.user_data = user_data_removed;
+                }
+                self.objects
+                    .insert(user_data_removed, (rigid_body, collider));
+            }
+        }
+    }
+
+    /// Hash of every body position and velocity, for checking that two runs
+    /// match. Recordings store it, so it's FNV-1a rather than a std hasher
+    /// that may change between Rust releases.
+    pub fn state_hash(&self) -> u64 {
+        let mut hash = FNV_OFFSET;
+
+        for (_, body) in self.physics.bodies.iter() {
+            let position = body.position();
+            position
+                .translation
+                .vector
+                .iter()
+                .chain(position.rotation.coords.iter())
+                .chain(body.linvel().iter())
+                .chain(body.angvel().iter())
+                .for_each(|v| hash = fnv1a(hash, &v.to_le_bytes()));
+            hash = fnv1a(hash, &[body.is_enabled() as u8]);
+        }
+
+        hash
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_matches_reference() {
+        assert_eq!(fnv1a(FNV_OFFSET, b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(FNV_OFFSET, b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(FNV_OFFSET, b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn state_hash_is_pinned() {
+        // Recordings from earlier builds must keep matching. Nothing is
+        // stepped, so the state doesn't depend on float behavior.
+        let mut world = World::new();
+        world.throw_ball(Vec3::new(0.0, 1.0, 2.0), Vec3::NEG_Z, 15.0);
+        assert_eq!(world.state_hash(), 0x8a12_2e62_15a6_c056);
+    }
+}